@@ -2,7 +2,7 @@ use std::pin::Pin;
 use std::sync::Mutex;
 use std::task::{Context, Poll};
 use std::time::Duration;
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 
 use actix_web::web;
 use actix_web::{Error};
@@ -10,17 +10,83 @@ use futures::{Stream, StreamExt};
 use tokio::sync::mpsc::{channel, Receiver, Sender};
 use tokio::time::{interval_at, Instant};
 
-pub fn broadcast(
-    event: String,
-    msg: String,
-    broadcaster: web::Data<Mutex<Broadcaster>>,
-) -> () {
-    broadcaster.lock().unwrap().send(&event, &msg);
+use crate::constants;
+
+/// Values an event can be filtered on, e.g. `station` -> [`bar`, `grill`]
+pub type EventTags = HashMap<String, Vec<String>>;
+
+/// The tag of events that only go to subscribers asking for them by name under
+/// the same key, e.g. `opt_in` -> `item_recipes`
+pub const OPT_IN: &str = "opt_in";
+
+/// An event as handed to subscribers, before it is written for a transport
+#[derive(Debug, Clone)]
+pub struct Event {
+    pub id: Option<u64>,
+    pub name: String,
+    pub data: String,
 }
 
+impl Event {
+    pub fn new(name: &str, data: &str) -> Self {
+        Event {
+            id: None,
+            name: name.to_string(),
+            data: data.to_string(),
+        }
+    }
+
+    /// The event in the `text/event-stream` format
+    pub fn to_sse(&self) -> web::Bytes {
+        let data = match self.id {
+            Some(id) => format!("id: {}\nevent: {}\ndata: {}\n\n", id, self.name, self.data),
+            None => format!("event: {}\ndata: {}\n\n", self.name, self.data),
+        };
+
+        web::Bytes::from(data)
+    }
+
+    /// The event as a JSON object, with JSON data embedded rather than quoted
+    pub fn to_json(&self) -> String {
+        let data = serde_json::from_str(&self.data)
+            .unwrap_or_else(|_| serde_json::Value::String(self.data.clone()));
+
+        serde_json::json!({ "id": self.id, "event": self.name, "data": data }).to_string()
+    }
+}
+
+struct Subscriber {
+    sender: Sender<Event>,
+    // tag values an event must carry to be sent to this subscriber; an event without
+    // the key, like a product change, concerns every display and is sent anyway
+    filter: HashMap<String, String>,
+}
+
+impl Subscriber {
+    fn accepts(&self, tags: &EventTags) -> bool {
+        let opted_in = match tags.get(OPT_IN) {
+            Some(events) => self.filter.get(OPT_IN).is_some_and(|event| events.contains(event)),
+            None => true,
+        };
+
+        opted_in
+            && self.filter.iter().filter(|(key, _)| *key != OPT_IN).all(|(key, value)| {
+                tags.get(key)
+                    .is_none_or(|values| values.contains(value))
+            })
+    }
+}
+
+// an event kept for clients that reconnect with `Last-Event-ID`
+struct SentEvent {
+    event: Event,
+    tags: EventTags,
+}
 
 pub struct Broadcaster {
-    clients: Vec<Sender<web::Bytes>>,
+    clients: Vec<Subscriber>,
+    history: VecDeque<SentEvent>,
+    next_id: u64,
 }
 
 impl Broadcaster {
@@ -35,8 +101,13 @@ impl Broadcaster {
     }
 
     pub fn new() -> Self {
+        // start from the clock so ids keep increasing across restarts
+        let first_id = chrono::Utc::now().timestamp_micros().max(0) as u64;
+
         Broadcaster {
-            clients: Vec::new()
+            clients: Vec::new(),
+            history: VecDeque::with_capacity(constants::EVENT_REPLAY_BUFFER_SIZE),
+            next_id: first_id,
         }
     }
 
@@ -51,58 +122,116 @@ impl Broadcaster {
     }
 
     pub fn remove_stale_clients(&mut self) {
-        let mut ok_clients = Vec::new();
-        for client in self.clients.iter() {
-            let result = client.clone().try_send(web::Bytes::from("event: internal_status\ndata: ping\n\n"));
+        let ping = Event::new("internal_status", "ping");
 
-            if let Ok(()) = result {
-                ok_clients.push(client.clone());
-            }
+        self.clients.retain(|client| client.sender.try_send(ping.clone()).is_ok());
+    }
+
+    /// Registers an event stream client, sending it the initial events in `collection`
+    ///
+    /// See `subscribe` for `filter` and `last_event_id`.
+    pub fn new_client(
+        &mut self,
+        collection: HashMap<String, String>,
+        filter: HashMap<String, String>,
+        last_event_id: Option<u64>,
+    ) -> Client
+    {
+        let mut initial: Vec<Event> = collection
+            .iter()
+            .map(|(event, message)| Event::new(event, message))
+            .collect();
+
+        if initial.is_empty() {
+            initial.push(Event::new("internal_status", "connected"));
         }
-        self.clients = ok_clients;
+
+        Client(self.subscribe(initial, filter, last_event_id))
     }
 
-    pub fn new_client(&mut self, collection: HashMap<String, String>) -> Client 
+    /// Registers a subscriber, which first receives the `initial` events
+    ///
+    /// A non-empty `filter` limits the subscriber to tagged events carrying every
+    /// one of its key/value pairs, apart from `OPT_IN`, which names the opt-in
+    /// events it receives on top of the others. A subscriber reconnecting with the id of the
+    /// last event it saw then receives the events it missed since then.
+    pub fn subscribe(
+        &mut self,
+        initial: Vec<Event>,
+        filter: HashMap<String, String>,
+        last_event_id: Option<u64>,
+    ) -> Receiver<Event>
     {
-        let (tx, rx) = channel(100);
+        let (tx, rx) = channel(100 + constants::EVENT_REPLAY_BUFFER_SIZE);
 
-        let tx_clone = tx.clone();
+        for event in initial {
+            tx.try_send(event).unwrap();
+        }
 
-        let mut new_collection : HashMap<String, String> = HashMap::new();
+        let subscriber = Subscriber { sender: tx, filter };
 
-        if collection.is_empty() {
-            new_collection.insert("internal_status".to_owned(), "connected".to_owned());
-        } else {
-            new_collection = collection;
+        if let Some(last_event_id) = last_event_id {
+            self.replay(&subscriber, last_event_id);
         }
 
-        for (evt, msg) in new_collection {
-            let msg = web::Bytes::from(["event: ", &evt, "\ndata: ", &msg, "\n\n"].concat());
+        self.clients.push(subscriber);
+
+        rx
+    }
+
+    /// Sends an untagged event, which only reaches clients without a filter
+    pub fn send(&mut self, event: &str, message: &str)
+    {
+        self.send_tagged(event, message, &EventTags::new());
+    }
+
+    /// Sends an event to every client whose filter `tags` satisfy
+    pub fn send_tagged(&mut self, event: &str, message: &str, tags: &EventTags)
+    {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let event = Event {
+            id: Some(id),
+            ..Event::new(event, message)
+        };
 
-            tx_clone.clone()
-                .try_send(msg)
-                .unwrap();
+        for client in self.clients.iter().filter(|client| client.accepts(tags)) {
+            client.sender.try_send(event.clone()).unwrap_or(());
+        }
 
-            self.clients.push(tx_clone.clone());
+        if self.history.len() == constants::EVENT_REPLAY_BUFFER_SIZE {
+            self.history.pop_front();
         }
 
-        Client(rx)
+        self.history.push_back(SentEvent { event, tags: tags.clone() });
     }
 
-    pub fn send(&self, event: &str, message: &str) 
-    {
-        let data = ["event: ", event, "\n", "data: ", message, "\n\n"].concat();
+    // sends the buffered events after `last_event_id` that the subscriber accepts
+    fn replay(&self, subscriber: &Subscriber, last_event_id: u64) {
+        let oldest_id = self
+            .history
+            .front()
+            .and_then(|sent| sent.event.id)
+            .unwrap_or(self.next_id);
+
+        if last_event_id.saturating_add(1) < oldest_id {
+            let truncated = Event::new("internal_status", "replay_truncated");
+            subscriber.sender.try_send(truncated).unwrap_or(());
+        }
 
-        let bytes = web::Bytes::from(data);
+        let missed = self.history.iter().filter(|sent| {
+            sent.event.id.is_some_and(|id| id > last_event_id) && subscriber.accepts(&sent.tags)
+        });
 
-        for client in self.clients.iter() {
-            client.clone().try_send(bytes.clone()).unwrap_or(());
+        for sent in missed {
+            subscriber.sender.try_send(sent.event.clone()).unwrap_or(());
         }
     }
 }
 
 // wrap Receiver in own type, with correct error type
-pub struct Client(Receiver<web::Bytes>);
+pub struct Client(Receiver<Event>);
 
 impl Stream for Client {
     type Item = Result<web::Bytes, Error>;
@@ -112,12 +241,189 @@ impl Stream for Client {
         cx: &mut Context<'_>,
     ) -> Poll<Option<Self::Item>> {
 
-        let mut new_pin_data : Pin::<&mut Receiver<web::Bytes>> = Pin::new(&mut self.0);
+        let mut new_pin_data : Pin::<&mut Receiver<Event>> = Pin::new(&mut self.0);
 
         match new_pin_data.poll_recv(cx) {
-            Poll::Ready(Some(v)) => Poll::Ready(Some(Ok(v))),
+            Poll::Ready(Some(v)) => Poll::Ready(Some(Ok(v.to_sse()))),
             Poll::Ready(None) => Poll::Ready(None),
             Poll::Pending => Poll::Pending,
         }
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::DomainEvent;
+
+    fn tags(pairs: &[(&str, &[&str])]) -> EventTags {
+        pairs
+            .iter()
+            .map(|(key, values)| {
+                (key.to_string(), values.iter().map(|value| value.to_string()).collect())
+            })
+            .collect()
+    }
+
+    fn filter(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect()
+    }
+
+    // the names of the events waiting for a subscriber
+    fn received(rx: &mut Receiver<Event>) -> Vec<String> {
+        std::iter::from_fn(|| rx.try_recv().ok()).map(|event| event.name).collect()
+    }
+
+    #[test]
+    fn sends_events_to_the_filters_they_satisfy() {
+        let mut broadcaster = Broadcaster::new();
+
+        let mut everything = broadcaster.subscribe(vec![], HashMap::new(), None);
+        let mut bar = broadcaster.subscribe(vec![], filter(&[("station", "bar")]), None);
+        let mut grill_ready = broadcaster.subscribe(
+            vec![],
+            filter(&[("station", "grill"), ("status", "Ready")]),
+            None,
+        );
+
+        broadcaster.send_tagged(
+            "drinks",
+            "{}",
+            &tags(&[("station", &["bar"]), ("status", &["Received"])]),
+        );
+        broadcaster.send_tagged(
+            "mixed",
+            "{}",
+            &tags(&[("station", &["bar", "grill"]), ("status", &["Ready"])]),
+        );
+        broadcaster.send("untagged", "{}");
+
+        assert_eq!(received(&mut everything), ["drinks", "mixed", "untagged"]);
+        assert_eq!(received(&mut bar), ["drinks", "mixed", "untagged"]);
+        assert_eq!(received(&mut grill_ready), ["mixed", "untagged"]);
+    }
+
+    #[actix_web::test]
+    async fn sends_product_events_to_every_display() {
+        let database = crate::database::tests::memory().await;
+        let broadcaster = web::Data::new(Mutex::new(Broadcaster::new()));
+        let mut grill =
+            broadcaster.lock().unwrap().subscribe(vec![], filter(&[("station", "grill")]), None);
+
+        let soup = serde_json::json!({ "name": "Soup", "price": "4.50", "kind": "Food" });
+        let product = crate::database::tests::product(&database, soup).await;
+
+        DomainEvent::ProductAvailabilityChanged { product }.publish(&broadcaster);
+
+        assert_eq!(received(&mut grill), ["product_availability_changed"]);
+
+        // order events still go only to the stations they concern
+        let bar = tags(&[("station", &["bar"]), ("status", &["Received"])]);
+        broadcaster.lock().unwrap().send_tagged("order_created", "{}", &bar);
+
+        assert!(received(&mut grill).is_empty());
+    }
+
+    #[test]
+    fn sends_opt_in_events_only_to_those_asking() {
+        let mut broadcaster = Broadcaster::new();
+
+        let mut plain = broadcaster.subscribe(vec![], filter(&[("station", "grill")]), None);
+        let mut cooks = broadcaster.subscribe(
+            vec![],
+            filter(&[("station", "grill"), (OPT_IN, "item_recipes")]),
+            None,
+        );
+
+        let station = ("station", &["grill"][..]);
+
+        broadcaster.send_tagged("order_created", "{}", &tags(&[station]));
+        broadcaster.send_tagged(
+            "item_recipes",
+            "{}",
+            &tags(&[station, (OPT_IN, &["item_recipes"])]),
+        );
+        broadcaster.send_tagged("other_opt_in", "{}", &tags(&[station, (OPT_IN, &["other"])]));
+
+        assert_eq!(received(&mut plain), ["order_created"]);
+        assert_eq!(received(&mut cooks), ["order_created", "item_recipes"]);
+    }
+
+    #[test]
+    fn replays_the_missed_events_a_subscriber_accepts() {
+        let mut broadcaster = Broadcaster::new();
+
+        let mut first = broadcaster.subscribe(vec![], filter(&[("station", "bar")]), None);
+
+        broadcaster.send_tagged("seen", "{}", &tags(&[("station", &["bar"])]));
+        let last_seen = first.try_recv().unwrap().id.unwrap();
+
+        broadcaster.send_tagged("missed", "{}", &tags(&[("station", &["bar"])]));
+        broadcaster.send_tagged("elsewhere", "{}", &tags(&[("station", &["grill"])]));
+        broadcaster.send_tagged("missed_too", "{}", &tags(&[("station", &["bar"])]));
+
+        let initial = vec![Event::new("internal_status", "connected")];
+        let bar = filter(&[("station", "bar")]);
+        let mut again = broadcaster.subscribe(initial, bar, Some(last_seen));
+
+        let replayed: Vec<Event> = std::iter::from_fn(|| again.try_recv().ok()).collect();
+        let names: Vec<&str> = replayed.iter().map(|event| event.name.as_str()).collect();
+
+        assert_eq!(names, ["internal_status", "missed", "missed_too"]);
+        assert!(replayed[1].id < replayed[2].id);
+
+        // live events follow the replayed ones
+        broadcaster.send_tagged("live", "{}", &tags(&[("station", &["bar"])]));
+
+        assert_eq!(received(&mut again), ["live"]);
+    }
+
+    #[test]
+    fn tells_subscribers_when_missed_events_are_gone() {
+        let mut broadcaster = Broadcaster::new();
+
+        let mut early = broadcaster.subscribe(vec![], HashMap::new(), None);
+
+        broadcaster.send("first", "{}");
+        let first = early.try_recv().unwrap().id.unwrap();
+
+        for _ in 0..constants::EVENT_REPLAY_BUFFER_SIZE {
+            broadcaster.send("later", "{}");
+        }
+
+        let mut late = broadcaster.subscribe(vec![], HashMap::new(), Some(first - 1));
+
+        assert_eq!(late.try_recv().unwrap().data, "replay_truncated");
+
+        let names = received(&mut late);
+
+        assert_eq!(names.len(), constants::EVENT_REPLAY_BUFFER_SIZE);
+
+        // a subscriber that saw the last event has nothing to catch up on
+        let last = first + constants::EVENT_REPLAY_BUFFER_SIZE as u64;
+        let mut current = broadcaster.subscribe(vec![], HashMap::new(), Some(last));
+
+        assert!(received(&mut current).is_empty());
+    }
+
+    #[test]
+    fn writes_events_for_each_transport() {
+        let event = Event { id: Some(7), ..Event::new("order_updated", r#"{"order":1}"#) };
+
+        assert_eq!(event.to_sse(), "id: 7\nevent: order_updated\ndata: {\"order\":1}\n\n");
+
+        let frame: serde_json::Value = serde_json::from_str(&event.to_json()).unwrap();
+
+        let expected =
+            serde_json::json!({ "id": 7, "event": "order_updated", "data": { "order": 1 } });
+
+        assert_eq!(frame, expected);
+
+        // data that is not JSON is sent as a string
+        let frame: serde_json::Value =
+            serde_json::from_str(&Event::new("internal_status", "ping").to_json()).unwrap();
+
+        assert_eq!(frame["data"], "ping");
+        assert!(frame["id"].is_null());
+    }
 }
\ No newline at end of file
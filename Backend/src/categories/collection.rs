@@ -0,0 +1,427 @@
+use log;
+use mongodb::bson::{oid::ObjectId, Bson, Document};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use super::model::*;
+use crate::products::{collection::ProductCollection, model::Product};
+use crate::storage::{Filter, Storage, StorageError};
+
+#[derive(Clone)]
+pub struct CategoryCollection
+{
+    collection_categories: Arc<dyn Storage<Category>>,
+}
+
+#[derive(Debug)]
+pub enum CategoryCollectionError
+{
+    CategoryNotFound,
+    CategoryNotModified,
+    /// The parent given for a category does not exist
+    ParentNotFound,
+    /// The parent given for a category is the category itself or one of its subcategories
+    ParentCycle,
+    /// The category still has subcategories or products
+    CategoryNotEmpty,
+    CustomError(String),
+}
+
+impl From<StorageError> for CategoryCollectionError
+{
+    fn from(error: StorageError) -> Self
+    {
+        error!("Category storage error: {:?}", error);
+        CategoryCollectionError::CustomError(error.to_string())
+    }
+}
+
+impl CategoryCollection
+{
+    pub async fn init(collection_categories: Arc<dyn Storage<Category>>) -> Self
+    {
+        CategoryCollection {
+            collection_categories,
+        }
+    }
+
+    pub async fn create(
+        &self,
+        content: CategoryCreateRequest,
+    ) -> Result<Category, CategoryCollectionError>
+    {
+        info!("Creating category...");
+
+        let parent = match content.parent
+        {
+            Some(parent) => Some(self.parent(&parent).await?),
+            None => None,
+        };
+
+        let mut category = Category {
+            id: None,
+            name: content.name,
+            parent,
+            position: content.position.unwrap_or(0),
+        };
+
+        let id = self.collection_categories.insert(category.clone()).await?;
+
+        category.id = Some(id);
+
+        Ok(category)
+    }
+
+    pub async fn list(
+        &self,
+        offset: u64,
+        limit: i64,
+    ) -> Result<Vec<Category>, CategoryCollectionError>
+    {
+        info!("Listing categories...");
+
+        Ok(self.collection_categories.list(offset, limit).await?)
+    }
+
+    pub async fn get(&self, id: ObjectId) -> Result<Category, CategoryCollectionError>
+    {
+        info!("Getting category by id...");
+
+        match self.collection_categories.get(&id).await?
+        {
+            Some(category) => Ok(category),
+            None =>
+            {
+                error!("Category not found.");
+                Err(CategoryCollectionError::CategoryNotFound)
+            },
+        }
+    }
+
+    /// Whether a category with the given id exists
+    pub async fn exists(&self, id: ObjectId) -> Result<bool, CategoryCollectionError>
+    {
+        Ok(self.collection_categories.get(&id).await?.is_some())
+    }
+
+    /// Changes the fields given in `content` and returns the updated category
+    pub async fn update(
+        &self,
+        id: ObjectId,
+        content: CategoryUpdateRequest,
+    ) -> Result<Category, CategoryCollectionError>
+    {
+        info!("Updating category...");
+
+        self.get(id).await?;
+
+        let mut update = Document::new();
+
+        if let Some(name) = content.name
+        {
+            update.insert("name", name);
+        }
+
+        match content.parent
+        {
+            Some(Some(parent)) =>
+            {
+                let parent = self.parent(&parent).await?;
+
+                if self.is_within(parent, id).await?
+                {
+                    error!("Category cannot be moved under itself.");
+                    return Err(CategoryCollectionError::ParentCycle);
+                }
+
+                update.insert("parent", parent);
+            },
+            Some(None) =>
+            {
+                update.insert("parent", Bson::Null);
+            },
+            None => (),
+        }
+
+        if let Some(position) = content.position
+        {
+            update.insert("position", position);
+        }
+
+        let result = self.collection_categories.update(&id, update).await?;
+
+        if !result.matched
+        {
+            error!("Category not found.");
+            return Err(CategoryCollectionError::CategoryNotFound);
+        }
+
+        if !result.modified
+        {
+            error!("Category not modified.");
+            return Err(CategoryCollectionError::CategoryNotModified);
+        }
+
+        self.get(id).await
+    }
+
+    /// Deletes an empty category, returning it as it was
+    pub async fn delete(
+        &self,
+        id: ObjectId,
+        collection_products: &ProductCollection,
+    ) -> Result<Category, CategoryCollectionError>
+    {
+        info!("Deleting category by id...");
+
+        let category = self.get(id).await?;
+
+        let subcategory = self.collection_categories.find_one(&Filter::eq("parent", id)).await?;
+
+        let products = collection_products
+            .list_in_category(id)
+            .await
+            .map_err(|error| CategoryCollectionError::CustomError(format!("{:?}", error)))?;
+
+        if subcategory.is_some() || !products.is_empty()
+        {
+            error!("Category is not empty.");
+            return Err(CategoryCollectionError::CategoryNotEmpty);
+        }
+
+        match self.collection_categories.delete(&id).await?
+        {
+            true => Ok(category),
+            false => Err(CategoryCollectionError::CategoryNotFound),
+        }
+    }
+
+    /// Builds the menu tree from every category and product
+    ///
+    /// Products whose category no longer exists are listed outside of any section.
+    pub async fn menu(
+        &self,
+        collection_products: &ProductCollection,
+    ) -> Result<Menu, CategoryCollectionError>
+    {
+        info!("Building menu...");
+
+        let mut categories = self.collection_categories.list(0, 0).await?;
+
+        let mut products = collection_products
+            .list(&Filter::All, None, 0, 0)
+            .await
+            .map_err(|error| CategoryCollectionError::CustomError(format!("{:?}", error)))?;
+
+        categories.sort_by(|a, b| (a.position, &a.name).cmp(&(b.position, &b.name)));
+        products.sort_by(|a, b| (a.position, &a.name).cmp(&(b.position, &b.name)));
+
+        let known: Vec<ObjectId> = categories.iter().filter_map(|category| category.id).collect();
+
+        let mut children: HashMap<Option<ObjectId>, Vec<Category>> = HashMap::new();
+
+        for category in categories
+        {
+            // a dangling parent shows the category at the top
+            let parent = category.parent.filter(|parent| known.contains(parent));
+
+            children.entry(parent).or_default().push(category);
+        }
+
+        let mut contents: HashMap<Option<ObjectId>, Vec<Product>> = HashMap::new();
+
+        for product in products
+        {
+            let category = product.category.filter(|category| known.contains(category));
+
+            contents.entry(category).or_default().push(product);
+        }
+
+        Ok(Menu {
+            sections: Self::sections(None, &mut children, &mut contents),
+            products: contents.remove(&None).unwrap_or_default(),
+        })
+    }
+
+    fn sections(
+        parent: Option<ObjectId>,
+        children: &mut HashMap<Option<ObjectId>, Vec<Category>>,
+        contents: &mut HashMap<Option<ObjectId>, Vec<Product>>,
+    ) -> Vec<MenuSection>
+    {
+        let categories = children.remove(&parent).unwrap_or_default();
+
+        categories
+            .into_iter()
+            .map(|category| MenuSection {
+                products: contents.remove(&category.id).unwrap_or_default(),
+                sections: Self::sections(category.id, children, contents),
+                category,
+            })
+            .collect()
+    }
+
+    /// Parses and checks the parent given for a category
+    async fn parent(&self, parent: &str) -> Result<ObjectId, CategoryCollectionError>
+    {
+        let parent = ObjectId::parse_str(parent)
+            .map_err(|_| CategoryCollectionError::ParentNotFound)?;
+
+        match self.exists(parent).await?
+        {
+            true => Ok(parent),
+            false =>
+            {
+                error!("Parent category not found.");
+                Err(CategoryCollectionError::ParentNotFound)
+            },
+        }
+    }
+
+    /// Whether `category` is `ancestor` or one of its subcategories
+    async fn is_within(
+        &self,
+        category: ObjectId,
+        ancestor: ObjectId,
+    ) -> Result<bool, CategoryCollectionError>
+    {
+        let mut current = Some(category);
+        let mut visited = Vec::new();
+
+        while let Some(id) = current
+        {
+            if id == ancestor
+            {
+                return Ok(true);
+            }
+
+            // stop on cycles left by concurrent moves rather than loop forever
+            if visited.contains(&id)
+            {
+                return Ok(false);
+            }
+
+            visited.push(id);
+
+            current = self.collection_categories.get(&id).await?.and_then(|found| found.parent);
+        }
+
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::database::tests::{memory, product};
+    use crate::database::Database;
+
+    async fn category(
+        database: &Database,
+        name: &str,
+        parent: Option<ObjectId>,
+        position: i32,
+    ) -> ObjectId
+    {
+        let content = serde_json::from_value(json!({
+            "name": name,
+            "parent": parent.map(|parent| parent.to_hex()),
+            "position": position,
+        }))
+        .unwrap();
+
+        database.categories().await.create(content).await.unwrap().id.unwrap()
+    }
+
+    fn dish(name: &str, category: ObjectId) -> Value
+    {
+        json!({ "name": name, "price": "5.00 EUR", "kind": "Food", "category": category.to_hex() })
+    }
+
+    async fn move_under(
+        database: &Database,
+        id: ObjectId,
+        parent: ObjectId,
+    ) -> Result<Category, CategoryCollectionError>
+    {
+        let content = serde_json::from_value(json!({ "parent": parent.to_hex() })).unwrap();
+
+        database.categories().await.update(id, content).await
+    }
+
+    fn names(sections: &[MenuSection]) -> Vec<&str>
+    {
+        sections.iter().map(|section| section.category.name.as_str()).collect()
+    }
+
+    #[actix_web::test]
+    async fn nests_sections_and_their_products_in_order()
+    {
+        let database = memory().await;
+        let (categories, products) = (database.categories().await, database.products().await);
+
+        let mains = category(&database, "Mains", None, 2).await;
+        let starters = category(&database, "Starters", None, 1).await;
+        let soups = category(&database, "Soups", Some(starters), 0).await;
+        let salads = category(&database, "Salads", Some(starters), 0).await;
+
+        product(&database, dish("Tomato soup", soups)).await;
+        product(&database, dish("Burger", mains)).await;
+        product(&database, json!({ "name": "Bread", "price": "5.00 EUR", "kind": "Food" })).await;
+
+        let menu = categories.menu(products).await.unwrap();
+
+        assert_eq!(names(&menu.sections), ["Starters", "Mains"]);
+        assert_eq!(names(&menu.sections[0].sections), ["Salads", "Soups"]);
+        assert_eq!(menu.sections[0].sections[1].products[0].name, "Tomato soup");
+        assert_eq!(menu.sections[1].products[0].name, "Burger");
+        assert_eq!(menu.products[0].name, "Bread");
+
+        // empty categories can go, and new ones take their place by position
+        categories.delete(salads, products).await.unwrap();
+        category(&database, "Sides", None, 3).await;
+
+        let menu = categories.menu(products).await.unwrap();
+
+        assert_eq!(names(&menu.sections), ["Starters", "Mains", "Sides"]);
+        assert_eq!(names(&menu.sections[0].sections), ["Soups"]);
+    }
+
+    #[actix_web::test]
+    async fn keeps_the_tree_free_of_cycles_and_orphans()
+    {
+        let database = memory().await;
+        let (categories, products) = (database.categories().await, database.products().await);
+
+        let starters = category(&database, "Starters", None, 0).await;
+        let soups = category(&database, "Soups", Some(starters), 0).await;
+        let broths = category(&database, "Broths", Some(soups), 0).await;
+
+        for (id, parent) in [(starters, starters), (starters, broths)]
+        {
+            let moved = move_under(&database, id, parent).await;
+
+            assert!(matches!(moved, Err(CategoryCollectionError::ParentCycle)));
+        }
+
+        let moved = move_under(&database, broths, starters).await.unwrap();
+
+        assert_eq!(moved.parent, Some(starters));
+
+        let missing = move_under(&database, soups, ObjectId::new()).await;
+
+        assert!(matches!(missing, Err(CategoryCollectionError::ParentNotFound)));
+
+        product(&database, dish("Tomato soup", soups)).await;
+
+        for id in [starters, soups]
+        {
+            let deleted = categories.delete(id, products).await;
+
+            assert!(matches!(deleted, Err(CategoryCollectionError::CategoryNotEmpty)));
+        }
+    }
+}
\ No newline at end of file
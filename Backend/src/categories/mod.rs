@@ -0,0 +1,29 @@
+pub mod collection;
+pub mod model;
+pub mod service;
+
+use actix_web::web;
+
+pub fn config(config: &mut web::ServiceConfig)
+{
+    config
+        .service(
+            web::scope("/categories")
+            .service(
+                web::resource("")
+                .route(web::post().to(service::create))
+                .route(web::get().to(service::list))
+            )
+            .service(
+                web::resource("/{id}")
+                .route(web::get().to(service::get))
+                .route(web::put().to(service::update))
+                .route(web::patch().to(service::update))
+                .route(web::delete().to(service::delete))
+            )
+        )
+        .service(
+            web::resource("/menu")
+            .route(web::get().to(service::menu))
+        );
+}
\ No newline at end of file
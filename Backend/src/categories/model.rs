@@ -0,0 +1,89 @@
+use mongodb::bson::oid::ObjectId;
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+use crate::common_model;
+use crate::products::model::Product;
+use crate::storage::Entity;
+use crate::validation;
+
+/// A section of the menu, e.g. Soups, nested under its `parent`, e.g. Starters
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Category
+{
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent: Option<ObjectId>,
+    /// Where the category is shown among its siblings, lowest first
+    #[serde(default)]
+    pub position: i32,
+}
+
+impl Entity for Category
+{
+    fn id(&self) -> Option<ObjectId>
+    {
+        self.id
+    }
+
+    fn set_id(&mut self, id: ObjectId)
+    {
+        self.id = Some(id);
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CategoryListQuery
+{
+    pub offset: Option<u64>,
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Validate)]
+pub struct CategoryCreateRequest
+{
+    #[validate(
+        length(max = 100, message = "must be at most 100 characters"),
+        custom = "validation::not_blank"
+    )]
+    pub name: String,
+    #[validate(custom = "validation::object_id")]
+    pub parent: Option<String>,
+    pub position: Option<i32>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Validate)]
+pub struct CategoryUpdateRequest
+{
+    #[validate(
+        length(max = 100, message = "must be at most 100 characters"),
+        custom = "validation::not_blank"
+    )]
+    pub name: Option<String>,
+    /// `null` moves the category to the top of the menu
+    #[serde(default, deserialize_with = "common_model::deserialize_some")]
+    #[validate(custom = "validation::object_id")]
+    pub parent: Option<Option<String>>,
+    pub position: Option<i32>,
+}
+
+/// The whole menu, as returned by `GET /v1/menu`
+#[derive(Debug, Serialize, Clone)]
+pub struct Menu
+{
+    pub sections: Vec<MenuSection>,
+    /// Products that are not in any category
+    pub products: Vec<Product>,
+}
+
+/// A category with its products and subcategories, each in display order
+#[derive(Debug, Serialize, Clone)]
+pub struct MenuSection
+{
+    #[serde(flatten)]
+    pub category: Category,
+    pub products: Vec<Product>,
+    pub sections: Vec<MenuSection>,
+}
\ No newline at end of file
@@ -0,0 +1,106 @@
+use super::collection::*;
+use super::model::*;
+use crate::common_model::CommonResponse;
+use crate::database::Database;
+use crate::error::ApiError;
+use crate::extract::{EntityId, ValidJson};
+use actix_web::{web, HttpResponse};
+
+pub async fn create(
+    database_data: web::Data<Database>,
+    content: ValidJson<CategoryCreateRequest>,
+) -> Result<HttpResponse, ApiError>
+{
+    info!("Create Category requested...");
+
+    let collection = database_data.categories().await;
+
+    let category = collection.create(content.into_inner()).await?;
+
+    Ok(HttpResponse::Ok().json(category.id))
+}
+
+pub async fn list(
+    database_data: web::Data<Database>,
+    query: web::Query<CategoryListQuery>,
+) -> Result<HttpResponse, ApiError>
+{
+    info!("List Categories requested...");
+
+    let offset = query.offset.unwrap_or(0);
+    let limit = query.limit.unwrap_or(0);
+
+    let collection = database_data.categories().await;
+
+    let categories = collection.list(offset, limit).await?;
+
+    Ok(HttpResponse::Ok().json(categories))
+}
+
+pub async fn get(
+    database_data: web::Data<Database>,
+    id: EntityId,
+) -> Result<HttpResponse, ApiError>
+{
+    info!("Get Category by ID requested...");
+
+    let collection = database_data.categories().await;
+
+    let category = collection.get(id.into_inner()).await?;
+
+    Ok(HttpResponse::Ok().json(category))
+}
+
+pub async fn update(
+    database_data: web::Data<Database>,
+    id: EntityId,
+    content: ValidJson<CategoryUpdateRequest>,
+) -> Result<HttpResponse, ApiError>
+{
+    info!("Update Category requested...");
+
+    let collection = database_data.categories().await;
+
+    let category = collection
+        .update(id.into_inner(), content.into_inner())
+        .await?;
+
+    let response = CommonResponse::<Category> {
+        message: "1 categories updated.".to_string(),
+        data: Some(category),
+    };
+
+    Ok(HttpResponse::Ok().json(response))
+}
+
+pub async fn delete(
+    database_data: web::Data<Database>,
+    id: EntityId,
+) -> Result<HttpResponse, ApiError>
+{
+    let collection = database_data.categories().await;
+    let collection_products = database_data.products().await;
+
+    collection
+        .delete(id.into_inner(), collection_products)
+        .await?;
+
+    let response = CommonResponse::<Category> {
+        message: "1 categories deleted.".to_string(),
+        data: None,
+    };
+
+    Ok(HttpResponse::Ok().json(response))
+}
+
+pub async fn menu(database_data: web::Data<Database>) -> Result<HttpResponse, ApiError>
+{
+    info!("Menu requested...");
+
+    let collection = database_data.categories().await;
+    let collection_products = database_data.products().await;
+
+    let menu = collection.menu(collection_products).await?;
+
+    Ok(HttpResponse::Ok().json(menu))
+}
\ No newline at end of file
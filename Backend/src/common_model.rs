@@ -1,4 +1,6 @@
-use serde::{Serialize, Deserialize};
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use serde::de::{self, DeserializeOwned, IntoDeserializer};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct CommonResponse<T>
@@ -6,4 +8,119 @@ pub struct CommonResponse<T>
     pub message: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub data: Option<T>,
+}
+
+/// Tells a field that was left out (`None`) from one set to null (`Some(None)`),
+/// for updates that can clear a value; use together with `#[serde(default)]`
+pub fn deserialize_some<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    T::deserialize(deserializer).map(Some)
+}
+
+/// Reads a query parameter such as `nuts,gluten` as a list of values; use together
+/// with `#[serde(default)]`
+pub fn comma_separated<'de, T, D>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    T: DeserializeOwned,
+    D: Deserializer<'de>,
+{
+    let text = String::deserialize(deserializer)?;
+
+    text.split(',')
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .map(|value| T::deserialize(value.into_deserializer()))
+        .collect()
+}
+
+/// Stores a time as RFC 3339 text with all nine digits of the fraction, for fields
+/// that are filtered or sorted on; use with `#[serde(with = "common_model::stored_time")]`
+///
+/// Text with fewer digits does not sort like the time it gives (`…00.5Z` comes
+/// before `…00Z`), so stored times always have the same width. Clients still see
+/// the usual form, and any RFC 3339 text is read.
+pub mod stored_time
+{
+    use super::*;
+
+    /// The stored text of a time, which sorts like the times themselves
+    pub fn format(time: &DateTime<Utc>) -> String
+    {
+        time.format("%Y-%m-%dT%H:%M:%S%.9fZ").to_string()
+    }
+
+    pub fn serialize<S: Serializer>(time: &DateTime<Utc>, serializer: S) -> Result<S::Ok, S::Error>
+    {
+        if serializer.is_human_readable()
+        {
+            return time.serialize(serializer);
+        }
+
+        serializer.serialize_str(&format(time))
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<DateTime<Utc>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        DateTime::deserialize(deserializer)
+    }
+}
+
+/// A query parameter giving either a whole day (`2026-10-10`, in UTC) or a time
+/// (`2026-10-10T18:30:00Z`)
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+#[serde(untagged)]
+pub enum DateOrTime
+{
+    Date(NaiveDate),
+    Time(DateTime<Utc>),
+}
+
+impl DateOrTime
+{
+    /// The start of the day, or the time itself
+    pub fn start(&self) -> DateTime<Utc>
+    {
+        match self
+        {
+            // midnight exists on every day
+            DateOrTime::Date(date) => DateTime::from_utc(date.and_hms_opt(0, 0, 0).unwrap(), Utc),
+            DateOrTime::Time(time) => *time,
+        }
+    }
+
+    /// The start of the next day, so the whole day is included, or the time itself
+    pub fn end(&self) -> DateTime<Utc>
+    {
+        match self
+        {
+            // only the last day chrono knows has no next one
+            DateOrTime::Date(_) => self
+                .start()
+                .checked_add_signed(Duration::days(1))
+                .unwrap_or(DateTime::<Utc>::MAX_UTC),
+            DateOrTime::Time(time) => *time,
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for DateOrTime
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error>
+    {
+        let text = String::deserialize(deserializer)?;
+
+        if let Ok(date) = text.parse()
+        {
+            return Ok(DateOrTime::Date(date));
+        }
+
+        text.parse().map(DateOrTime::Time).map_err(|_| {
+            de::Error::custom(format!("'{}' is not a date or an RFC 3339 time", text))
+        })
+    }
 }
\ No newline at end of file
@@ -1 +1,8 @@
-pub const SERVER_PORT: u16 = 32112;
\ No newline at end of file
+pub const SERVER_PORT: u16 = 32112;
+pub const DEFAULT_STORAGE_BACKEND: &str = "mongo";
+pub const DEFAULT_SLED_PATH: &str = "data/KitchenManager";
+pub const DEFAULT_ORDER_NUMBER_RESET: &str = "daily";
+pub const DEFAULT_ORDER_NUMBER_RESET_HOUR: u32 = 0;
+pub const EVENT_REPLAY_BUFFER_SIZE: usize = 256;
+pub const DEFAULT_STATION_ROUTES: &str = "Food:kitchen,Coctail:bar,ReadyMade:pass,Other:pass";
+pub const DEFAULT_CURRENCY: &str = "USD";
\ No newline at end of file
@@ -1,48 +1,87 @@
-extern crate dotenv;
-use dotenv::dotenv;
-use futures::StreamExt;
-use mongodb::{
-    bson::{doc, extjson::de::Error},
-    results::{DeleteResult, InsertOneResult, UpdateResult},
-    Client, Collection,
-};
-use std::env;
-
+use crate::categories;
 use crate::console;
-use crate::orders::{self};
+use crate::ingredients;
+use crate::orders::{self, numbering::OrderNumbering};
 use crate::products::{self};
+use crate::stations::routing::StationRouting;
+use crate::storage::{Backend, StorageError};
 
 #[derive(Clone)]
 pub struct Database
 {
     collection_products: products::collection::ProductCollection,
     collection_orders: orders::collection::OrderCollection,
+    collection_categories: categories::collection::CategoryCollection,
+    collection_ingredients: ingredients::collection::IngredientCollection,
 }
 
 impl Database
 {
     pub async fn init() -> Self
     {
-        dotenv().ok();
+        let backend = Backend::from_env()
+            .await
+            .expect("Failed to initialize storage backend");
+
+        let database = Database::with_backend(backend)
+            .await
+            .expect("Failed to open storage collections");
+
+        database.migrate().await;
+
+        database
+    }
 
-        let uri = match env::var("MONGO_URI")
+    /// Brings documents written by older versions up to date
+    async fn migrate(&self)
+    {
+        match self.collection_products.migrate_prices().await
         {
-            Ok(key) => key.to_string(),
-            Err(_) => format!("Error loading env variable"),
-        };
+            Ok(0) => (),
+            Ok(count) => info!("Migrated prices of {} products.", count),
+            Err(error) => error!("Failed to migrate product prices. Error: {:?}", error),
+        }
 
-        let client = Client::with_uri_str(uri).await.unwrap();
+        match self.collection_orders.migrate_prices().await
+        {
+            Ok(0) => (),
+            Ok(count) => info!("Migrated totals of {} orders.", count),
+            Err(error) => error!("Failed to migrate order totals. Error: {:?}", error),
+        }
 
-        let database = client.database("KitchenManager");
+        match self.collection_orders.migrate_times().await
+        {
+            Ok(0) => (),
+            Ok(count) => info!("Migrated creation times of {} orders.", count),
+            Err(error) => error!("Failed to migrate order times. Error: {:?}", error),
+        }
+    }
 
+    pub async fn with_backend(backend: Backend) -> Result<Self, StorageError>
+    {
         let collection_products =
-            products::collection::ProductCollection::init(database.clone()).await;
-        let collection_orders = orders::collection::OrderCollection::init(database.clone()).await;
+            products::collection::ProductCollection::init(backend.collection("Products")?).await;
+        let collection_orders = orders::collection::OrderCollection::init(
+            backend.collection("Orders")?,
+            backend.counters("Counters")?,
+            OrderNumbering::from_env(),
+            StationRouting::from_env(),
+        )
+        .await;
+        let collection_categories =
+            categories::collection::CategoryCollection::init(backend.collection("Categories")?)
+                .await;
+        let collection_ingredients = ingredients::collection::IngredientCollection::init(
+            backend.collection("Ingredients")?,
+        )
+        .await;
 
-        Database {
+        Ok(Database {
             collection_products,
             collection_orders,
-        }
+            collection_categories,
+            collection_ingredients,
+        })
     }
 
     pub async fn products(&self) -> &products::collection::ProductCollection
@@ -54,4 +93,69 @@ impl Database
     {
         &self.collection_orders
     }
+
+    pub async fn categories(&self) -> &categories::collection::CategoryCollection
+    {
+        &self.collection_categories
+    }
+
+    pub async fn ingredients(&self) -> &ingredients::collection::IngredientCollection
+    {
+        &self.collection_ingredients
+    }
+}
+
+#[cfg(test)]
+pub mod tests
+{
+    use mongodb::bson::oid::ObjectId;
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::orders::{collection::OrderCollectionError, model::Order};
+    use crate::products::model::Product;
+    use crate::storage::memory::MemoryDatabase;
+
+    /// An empty database kept in memory
+    pub async fn memory() -> Database
+    {
+        Database::with_backend(Backend::Memory(MemoryDatabase::default())).await.unwrap()
+    }
+
+    /// Adds a product from the body a client would send
+    pub async fn product(database: &Database, content: Value) -> Product
+    {
+        let content = serde_json::from_value(content).unwrap();
+
+        database
+            .products()
+            .await
+            .create(content, database.categories().await, database.ingredients().await)
+            .await
+            .unwrap()
+    }
+
+    /// Places an order of `quantity` times each of `products`
+    pub async fn place(
+        database: &Database,
+        products: &[ObjectId],
+        quantity: i32,
+    ) -> Result<Order, OrderCollectionError>
+    {
+        let content = serde_json::from_value(json!({
+            "products": products
+                .iter()
+                .map(|id| json!({ "id": id.to_hex(), "quantity": quantity }))
+                .collect::<Vec<Value>>(),
+        }))
+        .unwrap();
+
+        let placed = database.orders().await.create(
+            content,
+            database.products().await,
+            database.ingredients().await,
+        );
+
+        placed.await.map(|(order, _, _)| order)
+    }
 }
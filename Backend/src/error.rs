@@ -0,0 +1,381 @@
+use actix_web::{http::StatusCode, web, HttpResponse, ResponseError};
+use mongodb::bson::oid::ObjectId;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::fmt;
+
+use crate::categories::collection::CategoryCollectionError;
+use crate::ingredients::collection::IngredientCollectionError;
+use crate::orders::{collection::OrderCollectionError, model::{ItemStatus, OrderStatus}};
+use crate::products::{collection::ProductCollectionError, model::Product};
+use crate::validation::{field_errors, FieldErrors};
+
+/// An error returned by the api, answered with an `ErrorResponse`
+#[derive(Debug)]
+pub enum ApiError
+{
+    /// The request could not be read, e.g. malformed JSON or query parameters
+    BadRequest(String),
+    /// An id that is not a well-formed `ObjectId`
+    InvalidId(String),
+    /// Fields of the request body that failed validation
+    Validation(FieldErrors),
+    ProductNotFound,
+    ProductNameExists,
+    ProductNotModified,
+    /// A product refers to a category that does not exist
+    ProductCategoryNotFound,
+    /// The recipe of a product takes an ingredient that does not exist
+    RecipeIngredientNotFound,
+    RecipeNotFound,
+    /// A product was "86"ed, or fewer than ordered remain
+    ProductUnavailable
+    {
+        id: Option<ObjectId>,
+        name: String,
+        remaining: Option<u32>,
+    },
+    CategoryNotFound,
+    CategoryNotModified,
+    /// A category refers to a parent that does not exist
+    ParentCategoryNotFound,
+    /// A category would become its own parent or ancestor
+    CategoryCycle,
+    /// A category to delete still has subcategories or products
+    CategoryNotEmpty,
+    IngredientNotFound,
+    IngredientNotModified,
+    /// An ingredient to delete is still used by a recipe
+    IngredientInUse,
+    OrderNotFound,
+    OrderItemNotFound,
+    OrderNotModified,
+    /// An order refers to a product that does not exist
+    OrderProductNotFound,
+    /// Prices that are added up are in different currencies
+    CurrencyMismatch,
+    /// The modifiers chosen for an order line do not fit the product
+    InvalidModifiers(String),
+    InvalidStatusTransition
+    {
+        from: OrderStatus,
+        to: OrderStatus,
+        allowed: Vec<OrderStatus>,
+    },
+    InvalidItemStatusTransition
+    {
+        from: ItemStatus,
+        to: ItemStatus,
+        allowed: Vec<ItemStatus>,
+    },
+    /// The order was changed by another request, the client should retry
+    OrderConflict,
+    /// No route matches the path of the request
+    RouteNotFound,
+    /// Details are logged, not returned to the client
+    Internal(String),
+}
+
+/// The body of every error response
+///
+/// Mirrors `CommonResponse`, adding a stable `code` clients can branch on.
+#[derive(Debug, Serialize, Clone)]
+pub struct ErrorResponse
+{
+    pub code: &'static str,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+}
+
+impl ApiError
+{
+    /// A machine-readable code that does not change with the message wording
+    pub fn code(&self) -> &'static str
+    {
+        match self
+        {
+            ApiError::BadRequest(_) => "bad_request",
+            ApiError::InvalidId(_) => "invalid_id",
+            ApiError::Validation(_) => "validation_failed",
+            ApiError::ProductNotFound => "product_not_found",
+            ApiError::ProductNameExists => "product_name_exists",
+            ApiError::ProductNotModified => "product_not_modified",
+            ApiError::ProductCategoryNotFound => "product_category_not_found",
+            ApiError::RecipeIngredientNotFound => "recipe_ingredient_not_found",
+            ApiError::RecipeNotFound => "recipe_not_found",
+            ApiError::ProductUnavailable { .. } => "product_unavailable",
+            ApiError::CategoryNotFound => "category_not_found",
+            ApiError::CategoryNotModified => "category_not_modified",
+            ApiError::ParentCategoryNotFound => "parent_category_not_found",
+            ApiError::CategoryCycle => "category_cycle",
+            ApiError::CategoryNotEmpty => "category_not_empty",
+            ApiError::IngredientNotFound => "ingredient_not_found",
+            ApiError::IngredientNotModified => "ingredient_not_modified",
+            ApiError::IngredientInUse => "ingredient_in_use",
+            ApiError::OrderNotFound => "order_not_found",
+            ApiError::OrderItemNotFound => "order_item_not_found",
+            ApiError::OrderNotModified => "order_not_modified",
+            ApiError::OrderProductNotFound => "order_product_not_found",
+            ApiError::CurrencyMismatch => "currency_mismatch",
+            ApiError::InvalidModifiers(_) => "invalid_modifiers",
+            ApiError::InvalidStatusTransition { .. } => "invalid_status_transition",
+            ApiError::InvalidItemStatusTransition { .. } => "invalid_item_status_transition",
+            ApiError::OrderConflict => "order_conflict",
+            ApiError::RouteNotFound => "route_not_found",
+            ApiError::Internal(_) => "internal_error",
+        }
+    }
+
+    /// The error for ordering a product that is not available
+    fn unavailable(product: Product) -> Self
+    {
+        ApiError::ProductUnavailable {
+            id: product.id,
+            name: product.name,
+            remaining: product.remaining,
+        }
+    }
+
+    pub fn to_response(&self) -> ErrorResponse
+    {
+        let data = match self
+        {
+            ApiError::Validation(fields) => serde_json::to_value(fields).ok(),
+            ApiError::InvalidStatusTransition { allowed, .. } => serde_json::to_value(allowed).ok(),
+            ApiError::InvalidItemStatusTransition { allowed, .. } =>
+            {
+                serde_json::to_value(allowed).ok()
+            },
+            ApiError::ProductUnavailable { id, name, remaining } => Some(json!({
+                "product": id.map(|id| id.to_hex()),
+                "name": name,
+                "remaining": remaining,
+            })),
+            _ => None,
+        };
+
+        ErrorResponse {
+            code: self.code(),
+            message: self.to_string(),
+            data,
+        }
+    }
+}
+
+impl fmt::Display for ApiError
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        match self
+        {
+            ApiError::BadRequest(message) => write!(f, "{}", message),
+            ApiError::InvalidId(id) => write!(f, "'{}' is not a valid id.", id),
+            ApiError::Validation(_) => write!(f, "Some fields of the request are not valid."),
+            ApiError::ProductNotFound => write!(f, "Product not found."),
+            ApiError::ProductNameExists => write!(f, "Product name already exist."),
+            ApiError::ProductNotModified => write!(f, "Product not modified."),
+            ApiError::ProductCategoryNotFound => write!(f, "Product category not found."),
+            ApiError::RecipeIngredientNotFound => write!(f, "Recipe ingredient not found."),
+            ApiError::RecipeNotFound => write!(f, "Product has no recipe."),
+            ApiError::ProductUnavailable { name, remaining: Some(remaining), .. }
+                if *remaining > 0 =>
+            {
+                write!(f, "Only {} of {} remain.", remaining, name)
+            },
+            ApiError::ProductUnavailable { name, .. } => write!(f, "{} is not available.", name),
+            ApiError::CategoryNotFound => write!(f, "Category not found."),
+            ApiError::CategoryNotModified => write!(f, "Category not modified."),
+            ApiError::ParentCategoryNotFound => write!(f, "Parent category not found."),
+            ApiError::CategoryCycle =>
+            {
+                write!(f, "Category cannot be moved under itself or its subcategories.")
+            },
+            ApiError::CategoryNotEmpty =>
+            {
+                write!(f, "Category still has subcategories or products.")
+            },
+            ApiError::IngredientNotFound => write!(f, "Ingredient not found."),
+            ApiError::IngredientNotModified => write!(f, "Ingredient not modified."),
+            ApiError::IngredientInUse => write!(f, "Ingredient is still used by a recipe."),
+            ApiError::OrderNotFound => write!(f, "Order not found."),
+            ApiError::OrderItemNotFound => write!(f, "Order item not found."),
+            ApiError::OrderNotModified => write!(f, "Order not modified."),
+            ApiError::OrderProductNotFound => write!(f, "One of products not found."),
+            ApiError::CurrencyMismatch =>
+            {
+                write!(f, "Prices that add up must be in the same currency.")
+            },
+            ApiError::InvalidModifiers(message) => write!(f, "{}", message),
+            ApiError::InvalidStatusTransition { from, to, .. } =>
+            {
+                write!(f, "Order status cannot change from {:?} to {:?}.", from, to)
+            },
+            ApiError::InvalidItemStatusTransition { from, to, .. } =>
+            {
+                write!(f, "Order item status cannot change from {:?} to {:?}.", from, to)
+            },
+            ApiError::OrderConflict =>
+            {
+                write!(f, "Order was changed by another request, please retry.")
+            },
+            ApiError::RouteNotFound => write!(f, "No such route."),
+            ApiError::Internal(_) => write!(f, "Internal server error."),
+        }
+    }
+}
+
+impl ResponseError for ApiError
+{
+    fn status_code(&self) -> StatusCode
+    {
+        match self
+        {
+            ApiError::BadRequest(_)
+            | ApiError::InvalidId(_)
+            | ApiError::Validation(_)
+            | ApiError::OrderProductNotFound
+            | ApiError::CurrencyMismatch
+            | ApiError::InvalidModifiers(_)
+            | ApiError::ProductCategoryNotFound
+            | ApiError::RecipeIngredientNotFound
+            | ApiError::ParentCategoryNotFound => StatusCode::BAD_REQUEST,
+            ApiError::ProductNotFound
+            | ApiError::RecipeNotFound
+            | ApiError::CategoryNotFound
+            | ApiError::IngredientNotFound
+            | ApiError::OrderNotFound
+            | ApiError::OrderItemNotFound
+            | ApiError::RouteNotFound => StatusCode::NOT_FOUND,
+            ApiError::ProductNameExists
+            | ApiError::ProductNotModified
+            | ApiError::CategoryNotModified
+            | ApiError::ProductUnavailable { .. }
+            | ApiError::CategoryCycle
+            | ApiError::CategoryNotEmpty
+            | ApiError::IngredientNotModified
+            | ApiError::IngredientInUse
+            | ApiError::OrderNotModified
+            | ApiError::InvalidStatusTransition { .. }
+            | ApiError::InvalidItemStatusTransition { .. }
+            | ApiError::OrderConflict => StatusCode::CONFLICT,
+            ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse
+    {
+        if let ApiError::Internal(details) = self
+        {
+            error!("Internal error: {}", details);
+        }
+
+        HttpResponse::build(self.status_code()).json(self.to_response())
+    }
+}
+
+impl From<validator::ValidationErrors> for ApiError
+{
+    fn from(errors: validator::ValidationErrors) -> Self
+    {
+        ApiError::Validation(field_errors(&errors))
+    }
+}
+
+impl From<ProductCollectionError> for ApiError
+{
+    fn from(error: ProductCollectionError) -> Self
+    {
+        match error
+        {
+            ProductCollectionError::ProductNameExists => ApiError::ProductNameExists,
+            ProductCollectionError::ProductNotFound => ApiError::ProductNotFound,
+            ProductCollectionError::ProductNotMofified => ApiError::ProductNotModified,
+            ProductCollectionError::CategoryNotFound => ApiError::ProductCategoryNotFound,
+            ProductCollectionError::IngredientNotFound => ApiError::RecipeIngredientNotFound,
+            ProductCollectionError::RecipeNotFound => ApiError::RecipeNotFound,
+            ProductCollectionError::CurrencyMismatch => ApiError::CurrencyMismatch,
+            ProductCollectionError::ProductUnavailable(product) => ApiError::unavailable(*product),
+            ProductCollectionError::CustomError(message) => ApiError::Internal(message),
+        }
+    }
+}
+
+impl From<CategoryCollectionError> for ApiError
+{
+    fn from(error: CategoryCollectionError) -> Self
+    {
+        match error
+        {
+            CategoryCollectionError::CategoryNotFound => ApiError::CategoryNotFound,
+            CategoryCollectionError::CategoryNotModified => ApiError::CategoryNotModified,
+            CategoryCollectionError::ParentNotFound => ApiError::ParentCategoryNotFound,
+            CategoryCollectionError::ParentCycle => ApiError::CategoryCycle,
+            CategoryCollectionError::CategoryNotEmpty => ApiError::CategoryNotEmpty,
+            CategoryCollectionError::CustomError(message) => ApiError::Internal(message),
+        }
+    }
+}
+
+impl From<IngredientCollectionError> for ApiError
+{
+    fn from(error: IngredientCollectionError) -> Self
+    {
+        match error
+        {
+            IngredientCollectionError::IngredientNotFound => ApiError::IngredientNotFound,
+            IngredientCollectionError::IngredientNotModified => ApiError::IngredientNotModified,
+            IngredientCollectionError::IngredientInUse => ApiError::IngredientInUse,
+            IngredientCollectionError::CustomError(message) => ApiError::Internal(message),
+        }
+    }
+}
+
+impl From<OrderCollectionError> for ApiError
+{
+    fn from(error: OrderCollectionError) -> Self
+    {
+        match error
+        {
+            OrderCollectionError::OneOfProductsNotFound => ApiError::OrderProductNotFound,
+            OrderCollectionError::OrderNotFound => ApiError::OrderNotFound,
+            OrderCollectionError::OrderNotModified => ApiError::OrderNotModified,
+            OrderCollectionError::ItemNotFound => ApiError::OrderItemNotFound,
+            OrderCollectionError::InvalidItemStatusTransition { from, to, allowed } =>
+            {
+                ApiError::InvalidItemStatusTransition { from, to, allowed }
+            },
+            OrderCollectionError::InvalidStatusTransition { from, to, allowed } =>
+            {
+                ApiError::InvalidStatusTransition { from, to, allowed }
+            },
+            OrderCollectionError::Conflict => ApiError::OrderConflict,
+            OrderCollectionError::CurrencyMismatch => ApiError::CurrencyMismatch,
+            OrderCollectionError::ProductUnavailable(product) => ApiError::unavailable(*product),
+            OrderCollectionError::InvalidModifiers(message) => ApiError::InvalidModifiers(message),
+            OrderCollectionError::CustomError(message) => ApiError::Internal(message),
+        }
+    }
+}
+
+/// Answers requests whose JSON body, query or path cannot be read with an `ApiError`,
+/// as well as requests no route matches
+pub fn config(config: &mut web::ServiceConfig)
+{
+    config
+        .app_data(web::JsonConfig::default().error_handler(|error, _| {
+            ApiError::BadRequest(format!("Invalid request body: {}.", error)).into()
+        }))
+        .app_data(web::QueryConfig::default().error_handler(|error, _| {
+            ApiError::BadRequest(format!("Invalid query: {}.", error)).into()
+        }))
+        .app_data(web::PathConfig::default().error_handler(|error, _| {
+            ApiError::BadRequest(format!("Invalid path: {}.", error)).into()
+        }))
+        .default_service(web::to(route_not_found));
+}
+
+async fn route_not_found() -> Result<HttpResponse, ApiError>
+{
+    Err(ApiError::RouteNotFound)
+}
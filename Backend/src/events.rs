@@ -0,0 +1,134 @@
+use actix_web::web;
+use serde::Serialize;
+use std::sync::Mutex;
+
+use crate::broadcast::{self, Broadcaster, EventTags};
+use crate::ingredients::model::Ingredient;
+use crate::orders::{model::{Note, Order, OrderItem}, stream::order_tags};
+use crate::products::model::{Product, RecipeView};
+
+/// What happened to a product in a `product_changed` event
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Change
+{
+    Created,
+    Updated,
+    Deleted,
+}
+
+/// The recipe of a line, for cooks to see how to make it
+#[derive(Debug, Serialize, Clone)]
+pub struct ItemRecipe
+{
+    pub index: usize,
+    pub quantity: i32,
+    pub recipe: RecipeView,
+}
+
+/// A change pushed to displays and the POS, carrying the entity as it is now
+///
+/// The event name is the `type` of its JSON data, e.g. `order_created`.
+#[derive(Debug, Serialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum DomainEvent
+{
+    OrderCreated { order: Order },
+    OrderUpdated { order: Order },
+    /// Carries the order as it was before it was deleted
+    OrderDeleted { order: Order },
+    ItemStatusChanged { order: Order, index: usize, item: Box<OrderItem> },
+    /// A note of the order, or of the line at `index`, was set or removed
+    NoteChanged { order: Order, index: Option<usize>, note: Option<Note> },
+    /// The recipes of the new lines of an order prepared at `station`; only sent to
+    /// displays that ask for it
+    ItemRecipes { order: Order, station: Option<String>, items: Vec<ItemRecipe> },
+    ProductChanged { change: Change, product: Product },
+    ProductAvailabilityChanged { product: Product },
+    /// What is on hand of the ingredient dropped to its `low_stock` threshold
+    StockLow { ingredient: Ingredient },
+}
+
+impl DomainEvent
+{
+    pub fn item_status_changed(order: Order, index: usize) -> Self
+    {
+        let item = Box::new(order.products[index].clone());
+
+        DomainEvent::ItemStatusChanged { order, index, item }
+    }
+
+    pub fn name(&self) -> &'static str
+    {
+        match self
+        {
+            DomainEvent::OrderCreated { .. } => "order_created",
+            DomainEvent::OrderUpdated { .. } => "order_updated",
+            DomainEvent::OrderDeleted { .. } => "order_deleted",
+            DomainEvent::ItemStatusChanged { .. } => "item_status_changed",
+            DomainEvent::NoteChanged { .. } => "note_changed",
+            DomainEvent::ItemRecipes { .. } => "item_recipes",
+            DomainEvent::ProductChanged { .. } => "product_changed",
+            DomainEvent::ProductAvailabilityChanged { .. } => "product_availability_changed",
+            DomainEvent::StockLow { .. } => "stock_low",
+        }
+    }
+
+    /// Order events are tagged like the order itself; recipes too, but with their own station
+    /// in place of the order's and as opt-in; product and stock events with the id of the
+    /// product or ingredient
+    pub fn tags(&self) -> EventTags
+    {
+        match self
+        {
+            DomainEvent::OrderCreated { order }
+            | DomainEvent::OrderUpdated { order }
+            | DomainEvent::OrderDeleted { order }
+            | DomainEvent::ItemStatusChanged { order, .. }
+            | DomainEvent::NoteChanged { order, .. } => order_tags(order),
+            DomainEvent::ItemRecipes { order, station, .. } =>
+            {
+                let mut tags = order_tags(order);
+
+                tags.insert("station".to_string(), station.iter().cloned().collect());
+                tags.insert(broadcast::OPT_IN.to_string(), vec![self.name().to_string()]);
+
+                tags
+            },
+            DomainEvent::ProductChanged { product, .. }
+            | DomainEvent::ProductAvailabilityChanged { product } =>
+            {
+                let mut tags = EventTags::new();
+
+                if let Some(id) = product.id
+                {
+                    tags.insert("product".to_string(), vec![id.to_hex()]);
+                }
+
+                tags
+            },
+            DomainEvent::StockLow { ingredient } =>
+            {
+                let mut tags = EventTags::new();
+
+                if let Some(id) = ingredient.id
+                {
+                    tags.insert("ingredient".to_string(), vec![id.to_hex()]);
+                }
+
+                tags
+            },
+        }
+    }
+
+    /// Sends the event to every subscriber whose filter it satisfies
+    pub fn publish(&self, broadcaster: &web::Data<Mutex<Broadcaster>>)
+    {
+        let data = serde_json::to_string(self).unwrap();
+
+        broadcaster
+            .lock()
+            .unwrap()
+            .send_tagged(self.name(), &data, &self.tags());
+    }
+}
@@ -0,0 +1,202 @@
+use actix_web::{dev::Payload, web, FromRequest, HttpRequest};
+use futures::future::{ready, LocalBoxFuture, Ready};
+use mongodb::bson::oid::ObjectId;
+use serde::de::DeserializeOwned;
+use validator::Validate;
+
+use crate::error::ApiError;
+
+/// The `{id}` segment of a route, parsed as an `ObjectId`
+///
+/// Malformed ids are answered with a 400 before the handler runs.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EntityId(pub ObjectId);
+
+impl EntityId
+{
+    pub fn into_inner(self) -> ObjectId
+    {
+        self.0
+    }
+}
+
+impl FromRequest for EntityId
+{
+    type Error = ApiError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(request: &HttpRequest, _: &mut Payload) -> Self::Future
+    {
+        let value = request.match_info().get("id").unwrap_or_default();
+
+        ready(parse_id(value).map(EntityId))
+    }
+}
+
+/// A JSON request body that passed its `Validate` rules
+///
+/// Invalid bodies are answered with a 400 listing the problem of each field.
+#[derive(Debug, Clone)]
+pub struct ValidJson<T>(pub T);
+
+impl<T> ValidJson<T>
+{
+    pub fn into_inner(self) -> T
+    {
+        self.0
+    }
+}
+
+impl<T: DeserializeOwned + Validate + 'static> FromRequest for ValidJson<T>
+{
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(request: &HttpRequest, payload: &mut Payload) -> Self::Future
+    {
+        let json = web::Json::<T>::from_request(request, payload);
+
+        Box::pin(async move {
+            let content = json.await?.into_inner();
+
+            content.validate().map_err(ApiError::from)?;
+
+            Ok(ValidJson(content))
+        })
+    }
+}
+
+/// Parses an id sent by a client, e.g. in a WebSocket command
+pub fn parse_id(value: &str) -> Result<ObjectId, ApiError>
+{
+    ObjectId::parse_str(value).map_err(|_| ApiError::InvalidId(value.to_string()))
+}
+
+#[cfg(test)]
+mod tests
+{
+    use actix_web::{http::StatusCode, test, web, App};
+    use mongodb::bson::oid::ObjectId;
+    use serde_json::{json, Value};
+    use std::sync::Mutex;
+
+    use crate::broadcast::Broadcaster;
+    use crate::database::tests::memory;
+
+    macro_rules! app {
+        () => {
+            test::init_service(
+                App::new()
+                    .app_data(web::Data::new(memory().await))
+                    .app_data(web::Data::new(Mutex::new(Broadcaster::new())))
+                    .configure(crate::config),
+            )
+            .await
+        };
+    }
+
+    #[actix_web::test]
+    async fn rejects_invalid_ids()
+    {
+        let app = app!();
+
+        let requests = vec![
+            test::TestRequest::get().uri("/v1/products/not-an-id"),
+            test::TestRequest::delete().uri("/v1/products/123"),
+            test::TestRequest::get().uri("/v1/orders/not-an-id"),
+            test::TestRequest::delete().uri("/v1/orders/zzzzzzzzzzzzzzzzzzzzzzzz"),
+            test::TestRequest::put()
+                .uri("/v1/orders/not-an-id/items/0")
+                .set_json(json!({ "status": "Ready" })),
+        ];
+
+        for request in requests
+        {
+            let response = test::call_service(&app, request.to_request()).await;
+
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+            let body: Value = test::read_body_json(response).await;
+
+            assert_eq!(body["code"], "invalid_id");
+        }
+    }
+
+    #[actix_web::test]
+    async fn answers_missing_ids_with_not_found()
+    {
+        let app = app!();
+
+        let missing = ObjectId::new().to_hex();
+
+        let requests = vec![
+            (test::TestRequest::get(), "products", "product_not_found"),
+            (test::TestRequest::delete(), "products", "product_not_found"),
+            (test::TestRequest::get(), "orders", "order_not_found"),
+            (test::TestRequest::delete(), "orders", "order_not_found"),
+        ];
+
+        for (request, resource, code) in requests
+        {
+            let request = request.uri(&format!("/v1/{}/{}", resource, missing));
+            let response = test::call_service(&app, request.to_request()).await;
+
+            assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+            let body: Value = test::read_body_json(response).await;
+
+            assert_eq!(body["code"], code);
+        }
+    }
+
+    #[actix_web::test]
+    async fn answers_unknown_routes_with_an_error_body()
+    {
+        let app = app!();
+
+        for uri in ["/v1/nothing-here", "/nothing-here", "/v1/orders/events/nothing-here"]
+        {
+            let request = test::TestRequest::get().uri(uri);
+            let response = test::call_service(&app, request.to_request()).await;
+
+            assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+            let body: Value = test::read_body_json(response).await;
+
+            assert_eq!(body["code"], "route_not_found");
+        }
+    }
+
+    #[actix_web::test]
+    async fn finds_valid_ids()
+    {
+        let app = app!();
+
+        let request = test::TestRequest::post()
+            .uri("/v1/products")
+            .set_json(json!({ "name": "Soup", "price": "4.50", "kind": "Food" }));
+        let product: Value = test::call_and_read_body_json(&app, request.to_request()).await;
+        let product = product["$oid"].as_str().unwrap().to_string();
+
+        let request = test::TestRequest::get().uri(&format!("/v1/products/{}", product));
+        let body: Value = test::call_and_read_body_json(&app, request.to_request()).await;
+
+        assert_eq!(body["name"], "Soup");
+
+        let request = test::TestRequest::post()
+            .uri("/v1/orders")
+            .set_json(json!({ "products": [{ "id": product, "quantity": 2 }] }));
+        let order: Value = test::call_and_read_body_json(&app, request.to_request()).await;
+        let order = order["$oid"].as_str().unwrap().to_string();
+
+        let request = test::TestRequest::get().uri(&format!("/v1/orders/{}", order));
+        let body: Value = test::call_and_read_body_json(&app, request.to_request()).await;
+
+        assert_eq!(body["products"][0]["id"], product);
+
+        let request = test::TestRequest::delete().uri(&format!("/v1/orders/{}", order));
+        let response = test::call_service(&app, request.to_request()).await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+}
@@ -0,0 +1,397 @@
+use log;
+use mongodb::bson::{doc, oid::ObjectId, Bson, Document};
+use std::sync::Arc;
+
+use super::model::*;
+use crate::products::collection::ProductCollection;
+use crate::storage::{self, Storage, StorageError};
+
+#[derive(Clone)]
+pub struct IngredientCollection
+{
+    collection_ingredients: Arc<dyn Storage<Ingredient>>,
+}
+
+#[derive(Debug)]
+pub enum IngredientCollectionError
+{
+    IngredientNotFound,
+    IngredientNotModified,
+    /// The ingredient is still part of the recipe of a product
+    IngredientInUse,
+    CustomError(String),
+}
+
+impl From<StorageError> for IngredientCollectionError
+{
+    fn from(error: StorageError) -> Self
+    {
+        error!("Ingredient storage error: {:?}", error);
+        IngredientCollectionError::CustomError(error.to_string())
+    }
+}
+
+impl IngredientCollection
+{
+    pub async fn init(collection_ingredients: Arc<dyn Storage<Ingredient>>) -> Self
+    {
+        IngredientCollection {
+            collection_ingredients,
+        }
+    }
+
+    pub async fn create(
+        &self,
+        content: IngredientCreateRequest,
+    ) -> Result<Ingredient, IngredientCollectionError>
+    {
+        info!("Creating ingredient...");
+
+        let mut ingredient = Ingredient {
+            id: None,
+            name: content.name,
+            unit: content.unit,
+            on_hand: round(content.on_hand),
+            low_stock: content.low_stock,
+        };
+
+        let id = self.collection_ingredients.insert(ingredient.clone()).await?;
+
+        ingredient.id = Some(id);
+
+        Ok(ingredient)
+    }
+
+    pub async fn list(
+        &self,
+        offset: u64,
+        limit: i64,
+    ) -> Result<Vec<Ingredient>, IngredientCollectionError>
+    {
+        info!("Listing ingredients...");
+
+        Ok(self.collection_ingredients.list(offset, limit).await?)
+    }
+
+    pub async fn get(&self, id: ObjectId) -> Result<Ingredient, IngredientCollectionError>
+    {
+        info!("Getting ingredient by id...");
+
+        match self.collection_ingredients.get(&id).await?
+        {
+            Some(ingredient) => Ok(ingredient),
+            None =>
+            {
+                error!("Ingredient not found.");
+                Err(IngredientCollectionError::IngredientNotFound)
+            },
+        }
+    }
+
+    /// Whether an ingredient with the given id exists
+    pub async fn exists(&self, id: ObjectId) -> Result<bool, IngredientCollectionError>
+    {
+        Ok(self.collection_ingredients.get(&id).await?.is_some())
+    }
+
+    /// Changes the fields given in `content` and returns the updated ingredient
+    pub async fn update(
+        &self,
+        id: ObjectId,
+        content: IngredientUpdateRequest,
+    ) -> Result<Ingredient, IngredientCollectionError>
+    {
+        info!("Updating ingredient...");
+
+        self.get(id).await?;
+
+        let mut update = Document::new();
+
+        if let Some(name) = content.name
+        {
+            update.insert("name", name);
+        }
+
+        if let Some(unit) = content.unit
+        {
+            update.insert("unit", storage::to_bson(&unit)?);
+        }
+
+        if let Some(on_hand) = content.on_hand
+        {
+            update.insert("on_hand", round(on_hand));
+        }
+
+        match content.low_stock
+        {
+            Some(Some(low_stock)) =>
+            {
+                update.insert("low_stock", low_stock);
+            },
+            Some(None) =>
+            {
+                update.insert("low_stock", Bson::Null);
+            },
+            None => (),
+        }
+
+        let result = self.collection_ingredients.update(&id, update).await?;
+
+        if !result.matched
+        {
+            error!("Ingredient not found.");
+            return Err(IngredientCollectionError::IngredientNotFound);
+        }
+
+        if !result.modified
+        {
+            error!("Ingredient not modified.");
+            return Err(IngredientCollectionError::IngredientNotModified);
+        }
+
+        self.get(id).await
+    }
+
+    /// Deletes an ingredient no recipe uses, returning it as it was
+    pub async fn delete(
+        &self,
+        id: ObjectId,
+        collection_products: &ProductCollection,
+    ) -> Result<Ingredient, IngredientCollectionError>
+    {
+        info!("Deleting ingredient by id...");
+
+        let ingredient = self.get(id).await?;
+
+        let products = collection_products
+            .list_using_ingredient(id)
+            .await
+            .map_err(|error| IngredientCollectionError::CustomError(format!("{:?}", error)))?;
+
+        if !products.is_empty()
+        {
+            error!("Ingredient is still used by a recipe.");
+            return Err(IngredientCollectionError::IngredientInUse);
+        }
+
+        match self.collection_ingredients.delete(&id).await?
+        {
+            true => Ok(ingredient),
+            false => Err(IngredientCollectionError::IngredientNotFound),
+        }
+    }
+
+    /// Takes the amounts used by an order from the stock, returning the ingredients
+    /// that ran low because of it
+    ///
+    /// Ingredients deleted since the order was placed are skipped. When one amount
+    /// cannot be taken, those taken before it are put back.
+    pub async fn take(
+        &self,
+        amounts: &[IngredientAmount],
+    ) -> Result<Vec<Ingredient>, IngredientCollectionError>
+    {
+        info!("Taking ingredients from stock...");
+
+        let mut low = Vec::new();
+
+        for (index, amount) in amounts.iter().enumerate()
+        {
+            let changed = match self.change_stock(amount.ingredient, -amount.quantity).await
+            {
+                Ok(changed) => changed,
+                Err(error) =>
+                {
+                    if let Err(error) = self.put_back(&amounts[..index]).await
+                    {
+                        error!("Failed to put back stock taken so far. Error: {:?}", error);
+                    }
+
+                    return Err(error);
+                },
+            };
+
+            if let Some((before, after)) = changed
+            {
+                if after.is_low() && !before.is_low()
+                {
+                    low.push(after);
+                }
+            }
+        }
+
+        Ok(low)
+    }
+
+    /// Puts the amounts taken for order lines that will not be served back into stock
+    pub async fn put_back(
+        &self,
+        amounts: &[IngredientAmount],
+    ) -> Result<(), IngredientCollectionError>
+    {
+        info!("Putting ingredients back into stock...");
+
+        for amount in amounts
+        {
+            self.change_stock(amount.ingredient, amount.quantity).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Adds `change` to what is on hand, retrying when another order changed it in
+    /// between; returns the ingredient before and after, or `None` if it is gone
+    async fn change_stock(
+        &self,
+        id: ObjectId,
+        change: f64,
+    ) -> Result<Option<(Ingredient, Ingredient)>, IngredientCollectionError>
+    {
+        const ATTEMPTS: usize = 10;
+
+        for _ in 0..ATTEMPTS
+        {
+            let before = match self.collection_ingredients.get(&id).await?
+            {
+                Some(ingredient) => ingredient,
+                None =>
+                {
+                    warn!("Ingredient {} no longer exists, stock not changed.", id);
+                    return Ok(None);
+                },
+            };
+
+            let mut after = before.clone();
+            after.on_hand = round(before.on_hand + change);
+
+            // too small to show at the precision stock is kept in
+            if after.on_hand == before.on_hand
+            {
+                return Ok(Some((before, after)));
+            }
+
+            let expected = doc! { "on_hand": before.on_hand };
+            let update = doc! { "on_hand": after.on_hand };
+
+            let result = self.collection_ingredients.update_if(&id, expected, update).await?;
+
+            if result.modified
+            {
+                return Ok(Some((before, after)));
+            }
+        }
+
+        Err(IngredientCollectionError::CustomError(
+            "Stock kept changing, giving up.".to_string(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use actix_web::{test, web, App};
+    use serde_json::{json, Value};
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::broadcast::Broadcaster;
+    use crate::categories::model::Category;
+    use crate::database::tests::{memory, place, product};
+    use crate::storage::{memory::MemoryDatabase, Backend};
+
+    async fn ingredient(ingredients: &IngredientCollection, content: Value) -> ObjectId
+    {
+        let content = serde_json::from_value(content).unwrap();
+
+        ingredients.create(content).await.unwrap().id.unwrap()
+    }
+
+    fn amount(ingredient: ObjectId, quantity: f64) -> IngredientAmount
+    {
+        IngredientAmount { ingredient, quantity }
+    }
+
+    #[actix_web::test]
+    async fn takes_stock_for_orders_and_puts_back_cancelled_lines()
+    {
+        let database = memory().await;
+        let ingredients = database.ingredients().await;
+
+        let leek = json!({ "name": "Leek", "unit": "g", "on_hand": 500.0 });
+        let leek = ingredient(ingredients, leek).await;
+        let on_hand = || async { ingredients.get(leek).await.unwrap().on_hand };
+
+        let recipe = json!({ "ingredients": [{ "ingredient": leek, "quantity": 120.0 }] });
+        let soup = json!({ "name": "Soup", "price": "4.50", "kind": "Food", "recipe": recipe });
+        let soup = product(&database, soup).await.id.unwrap();
+
+        let order = place(&database, &[soup], 2).await.unwrap();
+
+        assert_eq!(on_hand().await, 260.0);
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(database.clone()))
+                .app_data(web::Data::new(Mutex::new(Broadcaster::new())))
+                .configure(crate::config),
+        )
+        .await;
+
+        let request = test::TestRequest::put()
+            .uri(&format!("/v1/orders/{}/items/0", order.id.unwrap().to_hex()))
+            .set_json(json!({ "status": "Cancelled" }))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+
+        assert!(response.status().is_success());
+        assert_eq!(on_hand().await, 500.0);
+    }
+
+    #[actix_web::test]
+    async fn puts_back_what_was_taken_when_a_take_fails()
+    {
+        let backend = Backend::Memory(MemoryDatabase::default());
+        let ingredients =
+            IngredientCollection::init(backend.collection("Ingredients").unwrap()).await;
+
+        let leek = json!({ "name": "Leek", "unit": "g", "on_hand": 500.0 });
+        let leek = ingredient(&ingredients, leek).await;
+
+        // a document that cannot be read as an ingredient fails the take part way through
+        let categories = backend.collection::<Category>("Ingredients").unwrap();
+        let leftovers =
+            Category { id: None, name: "Leftovers".to_string(), parent: None, position: 0 };
+        let unreadable = categories.insert(leftovers).await.unwrap();
+
+        let taken = ingredients.take(&[amount(leek, 120.0), amount(unreadable, 1.0)]).await;
+
+        assert!(matches!(taken, Err(IngredientCollectionError::CustomError(_))));
+        assert_eq!(ingredients.get(leek).await.unwrap().on_hand, 500.0);
+    }
+
+    #[actix_web::test]
+    async fn warns_once_when_stock_drops_to_its_threshold()
+    {
+        let database = memory().await;
+        let ingredients = database.ingredients().await;
+
+        let leek = json!({ "name": "Leek", "unit": "g", "on_hand": 500.0, "low_stock": 200.0 });
+        let leek = ingredient(ingredients, leek).await;
+        let take = |quantity| async move { ingredients.take(&[amount(leek, quantity)]).await };
+
+        assert!(take(200.0).await.unwrap().is_empty());
+
+        let low = take(100.0).await.unwrap();
+
+        assert_eq!(low.len(), 1);
+        assert_eq!((low[0].id, low[0].on_hand), (Some(leek), 200.0));
+
+        // staying low is not news, dropping to it again after a delivery is
+        assert!(take(50.0).await.unwrap().is_empty());
+
+        ingredients.put_back(&[amount(leek, 400.0)]).await.unwrap();
+
+        assert_eq!(take(350.0).await.unwrap().len(), 1);
+    }
+}
\ No newline at end of file
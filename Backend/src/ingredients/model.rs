@@ -0,0 +1,132 @@
+use mongodb::bson::oid::ObjectId;
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+use crate::common_model;
+use crate::storage::Entity;
+use crate::validation;
+
+/// The unit an ingredient is counted in; recipes give their amounts in the same unit
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum Unit
+{
+    #[serde(rename = "g")]
+    Gram,
+    #[serde(rename = "kg")]
+    Kilogram,
+    #[serde(rename = "ml")]
+    Millilitre,
+    #[serde(rename = "l")]
+    Litre,
+    #[serde(rename = "piece")]
+    Piece,
+}
+
+/// Something the kitchen keeps in stock, e.g. flour or burger buns
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Ingredient
+{
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub name: String,
+    pub unit: Unit,
+    /// How much is in stock; goes below zero when more was used than was recorded
+    pub on_hand: f64,
+    /// `stock_low` is published when `on_hand` drops to this or below
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub low_stock: Option<f64>,
+}
+
+impl Ingredient
+{
+    pub fn is_low(&self) -> bool
+    {
+        self.low_stock.is_some_and(|low_stock| self.on_hand <= low_stock)
+    }
+}
+
+impl Entity for Ingredient
+{
+    fn id(&self) -> Option<ObjectId>
+    {
+        self.id
+    }
+
+    fn set_id(&mut self, id: ObjectId)
+    {
+        self.id = Some(id);
+    }
+}
+
+/// An amount of an ingredient, in its unit, as used by a recipe or an order line
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Validate)]
+pub struct IngredientAmount
+{
+    pub ingredient: ObjectId,
+    #[validate(custom = "validation::positive")]
+    pub quantity: f64,
+}
+
+impl IngredientAmount
+{
+    /// Adds up the amounts of each ingredient, keeping the order they first appear in
+    pub fn combine(amounts: impl IntoIterator<Item = IngredientAmount>) -> Vec<IngredientAmount>
+    {
+        let mut combined: Vec<IngredientAmount> = Vec::new();
+
+        for amount in amounts
+        {
+            match combined.iter_mut().find(|known| known.ingredient == amount.ingredient)
+            {
+                Some(known) => known.quantity = round(known.quantity + amount.quantity),
+                None => combined.push(amount),
+            }
+        }
+
+        combined
+    }
+}
+
+/// Rounds a quantity to thousandths, so repeated changes do not drift
+pub fn round(quantity: f64) -> f64
+{
+    (quantity * 1000.0).round() / 1000.0
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct IngredientListQuery
+{
+    pub offset: Option<u64>,
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Validate)]
+pub struct IngredientCreateRequest
+{
+    #[validate(
+        length(max = 100, message = "must be at most 100 characters"),
+        custom = "validation::not_blank"
+    )]
+    pub name: String,
+    pub unit: Unit,
+    #[serde(default)]
+    pub on_hand: f64,
+    #[validate(custom = "validation::not_negative")]
+    pub low_stock: Option<f64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Validate)]
+pub struct IngredientUpdateRequest
+{
+    #[validate(
+        length(max = 100, message = "must be at most 100 characters"),
+        custom = "validation::not_blank"
+    )]
+    pub name: Option<String>,
+    pub unit: Option<Unit>,
+    pub on_hand: Option<f64>,
+    /// `null` stops watching the stock of the ingredient
+    #[serde(default, deserialize_with = "common_model::deserialize_some")]
+    #[validate(custom = "validation::not_negative")]
+    pub low_stock: Option<Option<f64>>,
+}
\ No newline at end of file
@@ -0,0 +1,104 @@
+use super::collection::*;
+use super::model::*;
+use crate::broadcast::Broadcaster;
+use crate::common_model::CommonResponse;
+use crate::database::Database;
+use crate::error::ApiError;
+use crate::events::DomainEvent;
+use crate::extract::{EntityId, ValidJson};
+use actix_web::{web, HttpResponse};
+use std::sync::Mutex;
+
+pub async fn create(
+    database_data: web::Data<Database>,
+    content: ValidJson<IngredientCreateRequest>,
+) -> Result<HttpResponse, ApiError>
+{
+    info!("Create Ingredient requested...");
+
+    let collection = database_data.ingredients().await;
+
+    let ingredient = collection.create(content.into_inner()).await?;
+
+    Ok(HttpResponse::Ok().json(ingredient.id))
+}
+
+pub async fn list(
+    database_data: web::Data<Database>,
+    query: web::Query<IngredientListQuery>,
+) -> Result<HttpResponse, ApiError>
+{
+    info!("List Ingredients requested...");
+
+    let offset = query.offset.unwrap_or(0);
+    let limit = query.limit.unwrap_or(0);
+
+    let collection = database_data.ingredients().await;
+
+    let ingredients = collection.list(offset, limit).await?;
+
+    Ok(HttpResponse::Ok().json(ingredients))
+}
+
+pub async fn get(
+    database_data: web::Data<Database>,
+    id: EntityId,
+) -> Result<HttpResponse, ApiError>
+{
+    info!("Get Ingredient by ID requested...");
+
+    let collection = database_data.ingredients().await;
+
+    let ingredient = collection.get(id.into_inner()).await?;
+
+    Ok(HttpResponse::Ok().json(ingredient))
+}
+
+pub async fn update(
+    database_data: web::Data<Database>,
+    broadcaster: web::Data<Mutex<Broadcaster>>,
+    id: EntityId,
+    content: ValidJson<IngredientUpdateRequest>,
+) -> Result<HttpResponse, ApiError>
+{
+    info!("Update Ingredient requested...");
+
+    let collection = database_data.ingredients().await;
+
+    let ingredient = collection
+        .update(id.into_inner(), content.into_inner())
+        .await?;
+
+    // a stock count or a new threshold can show the ingredient is running low
+    if ingredient.is_low()
+    {
+        DomainEvent::StockLow { ingredient: ingredient.clone() }.publish(&broadcaster);
+    }
+
+    let response = CommonResponse::<Ingredient> {
+        message: "1 ingredients updated.".to_string(),
+        data: Some(ingredient),
+    };
+
+    Ok(HttpResponse::Ok().json(response))
+}
+
+pub async fn delete(
+    database_data: web::Data<Database>,
+    id: EntityId,
+) -> Result<HttpResponse, ApiError>
+{
+    let collection = database_data.ingredients().await;
+    let collection_products = database_data.products().await;
+
+    collection
+        .delete(id.into_inner(), collection_products)
+        .await?;
+
+    let response = CommonResponse::<Ingredient> {
+        message: "1 ingredients deleted.".to_string(),
+        data: None,
+    };
+
+    Ok(HttpResponse::Ok().json(response))
+}
\ No newline at end of file
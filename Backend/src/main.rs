@@ -21,12 +21,21 @@ use std::{
 extern crate log;
 
 mod broadcast;
+mod categories;
 mod common_model;
 mod console;
 mod constants;
 mod database;
+mod error;
+mod events;
+mod extract;
+mod ingredients;
+mod money;
 mod orders;
 mod products;
+mod stations;
+mod storage;
+mod validation;
 
 #[actix_rt::main]
 async fn main() -> io::Result<()> {
@@ -54,7 +63,7 @@ async fn main() -> io::Result<()> {
             .wrap(
                 Cors::default()
                     .send_wildcard()
-                    .allowed_methods(vec!["GET", "POST", "PUT", "DELETE"])
+                    .allowed_methods(vec!["GET", "POST", "PUT", "PATCH", "DELETE"])
                     // .allowed_headers(vec![
                     //     header::AUTHORIZATION,
                     //     header::ACCEPT,
@@ -64,18 +73,29 @@ async fn main() -> io::Result<()> {
             )
             .app_data(web::Data::new(database_manager.clone()))
             .app_data(web::Data::from(Arc::clone(&broadcast_manager)))
-            .service(
-                web::scope("/v1")
-                    .configure(products::config)
-                    .configure(orders::config),
-            )
-            .route("/", web::get().to(index))
+            .configure(config)
     })
     .bind(("0.0.0.0", constants::SERVER_PORT))?
     .run()
     .await
 }
 
+/// Registers every route of the api, so tests can build the same `App` as the server
+fn config(config: &mut web::ServiceConfig)
+{
+    config
+        .configure(error::config)
+        .service(
+            web::scope("/v1")
+                .configure(products::config)
+                .configure(categories::config)
+                .configure(ingredients::config)
+                .configure(orders::config)
+                .configure(stations::config),
+        )
+        .route("/", web::get().to(index));
+}
+
 async fn index() -> impl Responder {
     let content = r#"<html lang="en">
     <head>
@@ -100,11 +120,23 @@ async fn index() -> impl Responder {
 
             let events = new EventSource("/v1/orders/events/update");
             
-            events.addEventListener("order_update", (event) => {
+            let show = (event) => {
                 let data = document.createElement("p");
                 root.appendChild(data);
                 data.innerText = event.data;
-            });
+            };
+
+            let names = [
+                "order_created",
+                "order_updated",
+                "order_deleted",
+                "item_status_changed",
+                "note_changed",
+            ];
+
+            for (let name of names) {
+                events.addEventListener(name, show);
+            }
         </script>
     </body>
     </html>"#;
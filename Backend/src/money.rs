@@ -0,0 +1,381 @@
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::{env, fmt, str::FromStr, sync::OnceLock};
+
+use crate::constants;
+
+/// An ISO 4217 currency code, e.g. `EUR`
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Currency([u8; 3]);
+
+impl Currency
+{
+    /// Digits after the decimal point of the currency's minor unit
+    pub fn exponent(&self) -> u32
+    {
+        match self.as_str()
+        {
+            "BIF" | "CLP" | "DJF" | "GNF" | "ISK" | "JPY" | "KMF" | "KRW" | "PYG" | "RWF"
+            | "UGX" | "VND" | "VUV" | "XAF" | "XOF" | "XPF" => 0,
+            "BHD" | "IQD" | "JOD" | "KWD" | "LYD" | "OMR" | "TND" => 3,
+            _ => 2,
+        }
+    }
+
+    pub fn as_str(&self) -> &str
+    {
+        // only ever built from ASCII letters
+        std::str::from_utf8(&self.0).unwrap_or("???")
+    }
+}
+
+impl Default for Currency
+{
+    /// The currency read from `CURRENCY`, used for amounts given without one
+    fn default() -> Self
+    {
+        static DEFAULT: OnceLock<Currency> = OnceLock::new();
+
+        *DEFAULT.get_or_init(|| {
+            let code = env::var("CURRENCY")
+                .unwrap_or_else(|_| constants::DEFAULT_CURRENCY.to_string());
+
+            code.parse().unwrap_or_else(|error| {
+                warn!("{}, using {}.", error, constants::DEFAULT_CURRENCY);
+                constants::DEFAULT_CURRENCY.parse().unwrap()
+            })
+        })
+    }
+}
+
+impl FromStr for Currency
+{
+    type Err = MoneyError;
+
+    fn from_str(code: &str) -> Result<Self, Self::Err>
+    {
+        let bytes: [u8; 3] = code
+            .as_bytes()
+            .try_into()
+            .map_err(|_| MoneyError::InvalidCurrency(code.to_string()))?;
+
+        if !bytes.iter().all(u8::is_ascii_alphabetic)
+        {
+            return Err(MoneyError::InvalidCurrency(code.to_string()));
+        }
+
+        Ok(Currency(bytes.map(|byte| byte.to_ascii_uppercase())))
+    }
+}
+
+impl fmt::Display for Currency
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl fmt::Debug for Currency
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MoneyError
+{
+    InvalidAmount(String),
+    InvalidCurrency(String),
+}
+
+impl fmt::Display for MoneyError
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        match self
+        {
+            MoneyError::InvalidAmount(amount) => write!(f, "'{}' is not a valid amount", amount),
+            MoneyError::InvalidCurrency(code) => write!(f, "'{}' is not a currency code", code),
+        }
+    }
+}
+
+/// An exact amount of money, kept as a whole number of minor units (e.g. cents)
+///
+/// Appears in JSON as a string such as `"12.50 EUR"`; requests may leave out the
+/// currency, which then defaults to `CURRENCY`. Stored as `{ amount, currency }`
+/// with the amount in minor units, so the database holds it losslessly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Money
+{
+    minor: i64,
+    currency: Currency,
+}
+
+impl Money
+{
+    pub fn from_minor(minor: i64, currency: Currency) -> Self
+    {
+        Money { minor, currency }
+    }
+
+    pub fn zero(currency: Currency) -> Self
+    {
+        Money::from_minor(0, currency)
+    }
+
+    pub fn minor(&self) -> i64
+    {
+        self.minor
+    }
+
+    pub fn currency(&self) -> Currency
+    {
+        self.currency
+    }
+
+    pub fn is_negative(&self) -> bool
+    {
+        self.minor < 0
+    }
+
+    /// The sum of two amounts, `None` when their currencies differ or it overflows
+    pub fn checked_add(&self, other: Money) -> Option<Money>
+    {
+        if self.currency != other.currency
+        {
+            return None;
+        }
+
+        Some(Money::from_minor(self.minor.checked_add(other.minor)?, self.currency))
+    }
+
+    pub fn checked_mul(&self, quantity: i64) -> Option<Money>
+    {
+        Some(Money::from_minor(self.minor.checked_mul(quantity)?, self.currency))
+    }
+
+    /// Rounds a floating point amount, as prices were stored before, to minor units
+    fn from_float(amount: f64, currency: Currency) -> Result<Self, MoneyError>
+    {
+        let minor = (amount * 10_f64.powi(currency.exponent() as i32)).round();
+
+        if !minor.is_finite() || minor.abs() > i64::MAX as f64
+        {
+            return Err(MoneyError::InvalidAmount(amount.to_string()));
+        }
+
+        Ok(Money::from_minor(minor as i64, currency))
+    }
+
+    fn parse_amount(amount: &str, currency: Currency) -> Result<Self, MoneyError>
+    {
+        let invalid = || MoneyError::InvalidAmount(amount.to_string());
+
+        let (negative, digits) = match amount.strip_prefix('-')
+        {
+            Some(digits) => (true, digits),
+            None => (false, amount),
+        };
+
+        let (whole, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+
+        let exponent = currency.exponent() as usize;
+
+        let all_digits = |part: &str| part.bytes().all(|byte| byte.is_ascii_digit());
+
+        if whole.is_empty()
+            || !all_digits(whole)
+            || !all_digits(fraction)
+            || fraction.len() > exponent
+        {
+            return Err(invalid());
+        }
+
+        let scale = 10_i64.pow(exponent as u32);
+        let whole: i64 = whole.parse().map_err(|_| invalid())?;
+        let fraction: i64 = format!("{:0<width$}", fraction, width = exponent)
+            .parse()
+            .unwrap_or(0);
+
+        let minor = whole
+            .checked_mul(scale)
+            .and_then(|minor| minor.checked_add(fraction))
+            .ok_or_else(invalid)?;
+
+        Ok(Money::from_minor(if negative { -minor } else { minor }, currency))
+    }
+}
+
+impl Default for Money
+{
+    fn default() -> Self
+    {
+        Money::zero(Currency::default())
+    }
+}
+
+impl FromStr for Money
+{
+    type Err = MoneyError;
+
+    /// Parses `"12.50"` or `"12.50 EUR"`
+    fn from_str(text: &str) -> Result<Self, Self::Err>
+    {
+        let mut parts = text.split_whitespace();
+
+        let amount = parts.next().ok_or_else(|| MoneyError::InvalidAmount(text.to_string()))?;
+
+        let currency = match parts.next()
+        {
+            Some(code) => code.parse()?,
+            None => Currency::default(),
+        };
+
+        if parts.next().is_some()
+        {
+            return Err(MoneyError::InvalidAmount(text.to_string()));
+        }
+
+        Money::parse_amount(amount, currency)
+    }
+}
+
+impl fmt::Display for Money
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        let exponent = self.currency.exponent();
+        let scale = 10_u64.pow(exponent);
+        let sign = if self.is_negative() { "-" } else { "" };
+        let minor = self.minor.unsigned_abs();
+
+        match exponent
+        {
+            0 => write!(f, "{}{} {}", sign, minor, self.currency),
+            _ => write!(
+                f,
+                "{}{}.{:0width$} {}",
+                sign,
+                minor / scale,
+                minor % scale,
+                self.currency,
+                width = exponent as usize
+            ),
+        }
+    }
+}
+
+// the stored form of `Money`
+#[derive(Serialize, Deserialize)]
+struct StoredMoney
+{
+    amount: i64,
+    currency: String,
+}
+
+// every form `Money` is read from, including prices stored as plain numbers
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum MoneyRepr
+{
+    Stored(StoredMoney),
+    Text(String),
+    Number(f64),
+}
+
+impl Serialize for Money
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    {
+        if serializer.is_human_readable()
+        {
+            return serializer.collect_str(self);
+        }
+
+        StoredMoney {
+            amount: self.minor,
+            currency: self.currency.to_string(),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Money
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error>
+    {
+        let money = match MoneyRepr::deserialize(deserializer)?
+        {
+            MoneyRepr::Stored(stored) => stored
+                .currency
+                .parse()
+                .map(|currency| Money::from_minor(stored.amount, currency)),
+            MoneyRepr::Text(text) => text.parse(),
+            MoneyRepr::Number(amount) => Money::from_float(amount, Currency::default()),
+        };
+
+        money.map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn eur() -> Currency
+    {
+        "EUR".parse().unwrap()
+    }
+
+    #[test]
+    fn parses_and_prints_amounts()
+    {
+        let money: Money = "12.5 eur".parse().unwrap();
+
+        assert_eq!(money, Money::from_minor(1250, eur()));
+        assert_eq!(money.to_string(), "12.50 EUR");
+        assert_eq!("-0.05 EUR".parse::<Money>().unwrap().to_string(), "-0.05 EUR");
+        assert_eq!("1200 JPY".parse::<Money>().unwrap().minor(), 1200);
+
+        for invalid in ["", "1.005 EUR", "1,50 EUR", "1.50 EURO", ".5 EUR", "1.50 EUR x"]
+        {
+            assert!(invalid.parse::<Money>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn adds_without_drift()
+    {
+        let price: Money = "0.10 EUR".parse().unwrap();
+
+        let total = (0..10).fold(Money::zero(eur()), |total, _| {
+            total.checked_add(price).unwrap()
+        });
+
+        assert_eq!(total.to_string(), "1.00 EUR");
+        assert_eq!(price.checked_mul(3).unwrap().to_string(), "0.30 EUR");
+        assert_eq!(price.checked_add("1.00 USD".parse().unwrap()), None);
+    }
+
+    #[test]
+    fn stores_minor_units_and_reads_old_float_prices()
+    {
+        let money = Money::from_minor(410, eur());
+
+        let stored = crate::storage::to_bson(&money).unwrap();
+
+        assert_eq!(stored, mongodb::bson::bson!({ "amount": 410_i64, "currency": "EUR" }));
+        assert_eq!(mongodb::bson::from_bson::<Money>(stored).unwrap(), money);
+        assert_eq!(serde_json::to_string(&money).unwrap(), "\"4.10 EUR\"");
+
+        // an f32 price of 4.1 was stored as a double slightly below it
+        let old = mongodb::bson::Bson::Double(4.1_f32 as f64);
+        let money: Money = mongodb::bson::from_bson(old).unwrap();
+
+        assert_eq!(money.minor(), 410);
+    }
+}
@@ -1,21 +1,27 @@
-extern crate dotenv;
-use dotenv::dotenv;
-use futures::StreamExt;
-use mongodb::{
-    bson::{doc, extjson::de::Error, oid::ObjectId, self},
-    results::{DeleteResult, InsertOneResult, UpdateResult},
-    Client, Collection,
-};
-use std::{env, str::FromStr};
+use chrono::{DateTime, Utc};
+use mongodb::bson::{self, doc, oid::ObjectId, Document};
+use std::sync::Arc;
 
 use super::model::*;
+use super::numbering::OrderNumbering;
+use crate::common_model::stored_time;
 use crate::console;
-use crate::products;
+use crate::ingredients::{
+    collection::IngredientCollection,
+    model::{Ingredient, IngredientAmount},
+};
+use crate::products::{self, collection::ProductCollectionError, model::Product};
+use crate::stations::{model::StationQueueItem, routing::StationRouting};
+use crate::money::Money;
+use crate::storage::{self, Counters, Filter, Sort, Storage, UpdateOutcome};
 
 #[derive(Clone)]
 pub struct OrderCollection
 {
-    collection_order: Collection<Order>,
+    collection_order: Arc<dyn Storage<Order>>,
+    counters: Arc<dyn Counters>,
+    numbering: OrderNumbering,
+    routing: StationRouting,
 }
 
 #[derive(Debug)]
@@ -24,23 +30,71 @@ pub enum OrderCollectionError
     OneOfProductsNotFound,
     OrderNotFound,
     OrderNotModified,
+    ItemNotFound,
+    InvalidItemStatusTransition
+    {
+        from: ItemStatus,
+        to: ItemStatus,
+        allowed: Vec<ItemStatus>,
+    },
+    InvalidStatusTransition
+    {
+        from: OrderStatus,
+        to: OrderStatus,
+        allowed: Vec<OrderStatus>,
+    },
+    /// The order was changed by another request since it was read
+    Conflict,
+    /// The products are priced in different currencies
+    CurrencyMismatch,
+    /// A product is "86"ed, or fewer than ordered remain
+    ProductUnavailable(Box<Product>),
+    /// The modifiers chosen for a product do not fit its modifier groups
+    InvalidModifiers(String),
     CustomError(String),
 }
 
 impl OrderCollection
 {
+    /// Fields the order listing filters and sorts by, each group one index
+    const INDEXES: [&'static [&'static str]; 4] = [
+        &["created_at"],
+        &["status", "created_at"],
+        &["products.id"],
+        &["order_id"],
+    ];
+
     /// Creates a new instance of the OrderCollection
     ///
     /// # Arguments
     ///
-    /// * `database` - The database to use
+    /// * `collection_order` - The storage to use
+    /// * `counters` - The counters ticket numbers are drawn from
+    /// * `numbering` - When ticket numbers start again from one
+    /// * `routing` - Which station prepares each product
     ///
     ///
-    pub async fn init(database: mongodb::Database) -> Self
+    pub async fn init(
+        collection_order: Arc<dyn Storage<Order>>,
+        counters: Arc<dyn Counters>,
+        numbering: OrderNumbering,
+        routing: StationRouting,
+    ) -> Self
     {
-        let collection_order: Collection<Order> = database.collection("Orders");
+        for fields in Self::INDEXES
+        {
+            if let Err(error) = collection_order.index(fields).await
+            {
+                error!("Failed to index orders by {:?}. Error: {:?}", fields, error);
+            }
+        }
 
-        OrderCollection { collection_order }
+        OrderCollection {
+            collection_order,
+            counters,
+            numbering,
+            routing,
+        }
     }
 
     /// Create new order
@@ -49,6 +103,12 @@ impl OrderCollection
     ///
     /// * `content` - OrderCreateRequest
     /// * `products` - ProductCollection
+    /// * `ingredients` - IngredientCollection
+    ///
+    /// Takes the ordered quantities from the remaining counts of the products and
+    /// what their recipes use from stock, returning the products whose count changed
+    /// and the ingredients that ran low along with the order. Nothing is taken when
+    /// the order cannot be placed.
     ///
     /// # Examples
     ///
@@ -60,17 +120,27 @@ impl OrderCollection
         &self,
         content: OrderCreateRequest,
         collection_products: &products::collection::ProductCollection,
-    ) -> Result<InsertOneResult, OrderCollectionError>
+        collection_ingredients: &IngredientCollection,
+    ) -> Result<(Order, Vec<Product>, Vec<Ingredient>), OrderCollectionError>
     {
         info!("Creating order...");
 
-        let mut total_price = 0.0;
+        let now = chrono::Utc::now();
+
+        let mut total_price: Option<Money> = None;
 
-        let mut status: OrderStatus = OrderStatus::Completed;
+        let mut items: Vec<OrderItem> = Vec::new();
 
-        for product_view in content.clone().products
+        // the quantity ordered of each product, across all lines
+        let mut quantities: Vec<(Product, u32)> = Vec::new();
+
+        for product_view in content.products
         {
-            let product_id = product_view.id;
+            let product_id = match ObjectId::parse_str(&product_view.id)
+            {
+                Ok(product_id) => product_id,
+                Err(_) => return Err(OrderCollectionError::OneOfProductsNotFound),
+            };
 
             let product_result = collection_products.get(product_id).await;
 
@@ -78,65 +148,229 @@ impl OrderCollection
             {
                 Ok(product) =>
                 {
-                    total_price += product.price * product_view.quantity as f32;
+                    if !product.is_available()
+                    {
+                        return Err(OrderCollectionError::ProductUnavailable(Box::new(product)));
+                    }
 
-                    if product.kind != products::model::ProductKind::ReadyMade
+                    match quantities.iter_mut().find(|(ordered, _)| ordered.id == product.id)
                     {
-                        status = OrderStatus::Pending;
+                        Some((_, quantity)) => *quantity += product_view.quantity as u32,
+                        None => quantities.push((product.clone(), product_view.quantity as u32)),
                     }
+
+                    let modifiers = choose_modifiers(&product, &product_view.modifiers)
+                        .map_err(OrderCollectionError::InvalidModifiers)?;
+
+                    let station = self.routing.station_for(&product);
+
+                    let mut item = OrderItem::new(product_view, &product, modifiers, station);
+
+                    total_price = match (total_price, item.line_total)
+                    {
+                        (_, None) => return Err(Self::total_overflow()),
+                        (None, line_total) => line_total,
+                        (Some(total), Some(line_total)) =>
+                        {
+                            if total.currency() != line_total.currency()
+                            {
+                                return Err(OrderCollectionError::CurrencyMismatch);
+                            }
+
+                            Some(total.checked_add(line_total).ok_or_else(Self::total_overflow)?)
+                        },
+                    };
+
+                    // ready-made products have nothing to prepare
+                    if product.kind == products::model::ProductKind::ReadyMade
+                    {
+                        item.status = ItemStatus::Ready;
+                        item.ready_at = Some(now);
+                    }
+
+                    items.push(item);
+                },
+                Err(ProductCollectionError::ProductNotFound) =>
+                {
+                    return Err(OrderCollectionError::OneOfProductsNotFound)
+                },
+                Err(error) =>
+                {
+                    return Err(OrderCollectionError::CustomError(format!("{:?}", error)))
                 },
-                Err(error) => return Err(OrderCollectionError::OneOfProductsNotFound),
             }
         }
 
-        let new_order = Order {
+        if let Some((product, _)) = quantities
+            .iter()
+            .find(|(product, quantity)| product.remaining.is_some_and(|left| left < *quantity))
+        {
+            return Err(OrderCollectionError::ProductUnavailable(Box::new(product.clone())));
+        }
+
+        let status = OrderStatus::Received.derive_from(&items);
+
+        let reserved = Self::reserve(&quantities, collection_products).await?;
+
+        let order_period = self.numbering.period(now);
+
+        let order_id = match self.next_order_number(&order_period).await
+        {
+            Ok(order_id) => order_id,
+            Err(error) =>
+            {
+                Self::release(&reserved, collection_products).await;
+                return Err(error);
+            },
+        };
+
+        let mut new_order = Order {
             id: None,
-            order_id: 0,
-            products: content.products,
-            total_price: total_price,
-            status: status,
-            created_at: chrono::Utc::now(),
-            updated_at: chrono::Utc::now(),
+            order_id,
+            order_period,
+            products: items,
+            total_price: total_price.unwrap_or_default(),
+            status,
+            note: content.note,
+            allergy: false,
+            allergens: Vec::new(),
+            note_history: Vec::new(),
+            created_at: now,
+            updated_at: now,
+            previous_status: None,
         };
 
-        let result = self.collection_order.insert_one(new_order, None).await;
+        new_order.allergy = new_order.has_allergy_note();
+        new_order.allergens = new_order.line_allergens();
+
+        let stock = new_order.stock();
+
+        let low = match collection_ingredients.take(&stock).await
+        {
+            Ok(low) => low,
+            Err(error) =>
+            {
+                Self::release(&reserved, collection_products).await;
+                return Err(OrderCollectionError::CustomError(format!("{:?}", error)));
+            },
+        };
+
+        let result = self.collection_order.insert(new_order.clone()).await;
 
         match result
         {
-            Ok(result) => Ok(result),
-            Err(error) => Err(OrderCollectionError::CustomError(error.to_string())),
+            Ok(id) =>
+            {
+                new_order.id = Some(id);
+
+                let products = reserved.into_iter().map(|(product, _)| product).collect();
+
+                Ok((new_order, products, low))
+            },
+            Err(error) =>
+            {
+                Self::release(&reserved, collection_products).await;
+
+                if let Err(error) = collection_ingredients.put_back(&stock).await
+                {
+                    error!("Failed to put back stock of unplaced order. Error: {:?}", error);
+                }
+
+                Err(OrderCollectionError::CustomError(error.to_string()))
+            },
         }
     }
 
-    /// Get all orders
-    ///
-    ///
-    pub async fn list(&self, offset: u64, limit: i64) -> Result<Vec<Order>, OrderCollectionError>
+    /// Takes the ordered quantities from the products that count what remains,
+    /// giving everything back when one of them runs out in the meantime
+    async fn reserve(
+        quantities: &[(Product, u32)],
+        collection_products: &products::collection::ProductCollection,
+    ) -> Result<Vec<(Product, u32)>, OrderCollectionError>
     {
-        info!("Getting all orders...");
+        let mut reserved = Vec::new();
+
+        for (product, quantity) in quantities
+        {
+            let id = match product.id
+            {
+                Some(id) => id,
+                None => continue,
+            };
+
+            let result = collection_products.reserve(id, *quantity).await;
+
+            let error = match result
+            {
+                Ok(Some(product)) =>
+                {
+                    reserved.push((product, *quantity));
+                    continue;
+                },
+                Ok(None) => continue,
+                Err(ProductCollectionError::ProductUnavailable(product)) =>
+                {
+                    OrderCollectionError::ProductUnavailable(product)
+                },
+                Err(ProductCollectionError::ProductNotFound) =>
+                {
+                    OrderCollectionError::OneOfProductsNotFound
+                },
+                Err(error) => OrderCollectionError::CustomError(format!("{:?}", error)),
+            };
 
-        let find_options = mongodb::options::FindOptions::builder()
-            .skip(offset)
-            .limit(limit)
-            .build();
+            Self::release(&reserved, collection_products).await;
 
-        let mut cursor = self.collection_order.find(None, find_options).await.unwrap();
+            return Err(error);
+        }
 
-        let mut orders: Vec<Order> = Vec::new();
+        Ok(reserved)
+    }
 
-        while let Some(result) = cursor.next().await
+    /// Gives back quantities taken by `reserve` for an order that was not placed
+    async fn release(
+        reserved: &[(Product, u32)],
+        collection_products: &products::collection::ProductCollection,
+    )
+    {
+        for (product, quantity) in reserved
         {
-            match result
+            if let Some(id) = product.id
             {
-                Ok(document) =>
+                if let Err(error) = collection_products.release(id, *quantity).await
                 {
-                    orders.push(document);
-                },
-                Err(error) => return Err(OrderCollectionError::CustomError(error.to_string())),
+                    error!("Failed to give back reserved products. Error: {:?}", error);
+                }
             }
         }
+    }
 
-        Ok(orders)
+    fn total_overflow() -> OrderCollectionError
+    {
+        OrderCollectionError::CustomError("Order total is out of range.".to_string())
+    }
+
+    /// Get all orders
+    ///
+    /// Lists the orders matching `filter`, in the order of `sort` or as they were placed.
+    ///
+    pub async fn list(
+        &self,
+        filter: &Filter,
+        sort: Option<&Sort>,
+        offset: u64,
+        limit: i64,
+    ) -> Result<Vec<Order>, OrderCollectionError>
+    {
+        info!("Getting all orders...");
+
+        let result = self.collection_order.find_sorted(filter, sort, offset, limit).await;
+
+        match result
+        {
+            Ok(orders) => Ok(orders),
+            Err(error) => Err(OrderCollectionError::CustomError(error.to_string())),
+        }
     }
 
     /// Get a single order
@@ -152,15 +386,11 @@ impl OrderCollection
     /// // TODO
     ///
     /// ```
-    pub async fn get(&self, req_id: String) -> Result<Order, OrderCollectionError>
+    pub async fn get(&self, id: ObjectId) -> Result<Order, OrderCollectionError>
     {
         info!("Getting order...");
 
-        let id = ObjectId::from_str(&req_id).unwrap();
-
-        let filter = doc! { "_id": id };
-
-        let result = self.collection_order.find_one(filter, None).await;
+        let result = self.collection_order.get(&id).await;
 
         match result
         {
@@ -173,7 +403,94 @@ impl OrderCollection
         }
     }
 
-    /// Update a single order
+    /// Get a single order by its ticket number in the current numbering period
+    ///
+    /// # Arguments
+    ///
+    /// * `number` - i32
+    ///
+    pub async fn get_by_number(&self, number: i32) -> Result<Order, OrderCollectionError>
+    {
+        info!("Getting order by number...");
+
+        let order_period = self.numbering.period(chrono::Utc::now());
+
+        let filter = Filter::eq("order_id", number).and(Filter::eq("order_period", order_period));
+
+        let result = self.collection_order.find_one(&filter).await;
+
+        match result
+        {
+            Ok(Some(order)) => Ok(order),
+            Ok(None) => Err(OrderCollectionError::OrderNotFound),
+            Err(error) => Err(OrderCollectionError::CustomError(error.to_string())),
+        }
+    }
+
+    /// Get the open line items of a station, oldest order first
+    ///
+    /// # Arguments
+    ///
+    /// * `station` - Name of the station
+    ///
+    pub async fn station_queue(
+        &self,
+        station: &str,
+    ) -> Result<Vec<StationQueueItem>, OrderCollectionError>
+    {
+        info!("Getting station queue...");
+
+        let open_statuses = [
+            OrderStatus::Received,
+            OrderStatus::Accepted,
+            OrderStatus::InPreparation,
+            OrderStatus::Ready,
+        ];
+
+        let filter = Filter::one_of(
+            "status",
+            open_statuses.iter().map(|status| storage::to_bson(status).unwrap()).collect(),
+        )
+        .and(Filter::eq("products.station", station));
+
+        let orders = match self.collection_order.find(&filter, 0, 0).await
+        {
+            Ok(orders) => orders,
+            Err(error) => return Err(OrderCollectionError::CustomError(error.to_string())),
+        };
+
+        let mut queue: Vec<StationQueueItem> = Vec::new();
+
+        for order in orders
+        {
+            let items = order.products.into_iter().enumerate().filter(|(_, item)| {
+                item.station.as_deref() == Some(station)
+                    && matches!(item.status, ItemStatus::Queued | ItemStatus::InPreparation)
+            });
+
+            for (index, item) in items
+            {
+                queue.push(StationQueueItem {
+                    order: order.id,
+                    order_id: order.order_id,
+                    index,
+                    item,
+                    ordered_at: order.created_at,
+                });
+            }
+        }
+
+        queue.sort_by_key(|entry| entry.ordered_at);
+
+        Ok(queue)
+    }
+
+    /// Update the status of a single order
+    ///
+    /// Only the transitions listed by `OrderStatus::next_statuses` are allowed.
+    /// Setting the order `Ready` or `Served` moves its items along with it.
+    /// Returns what the lines it cancelled give back, the stock they took and their
+    /// product quantities.
     ///
     /// # Arguments
     ///
@@ -187,62 +504,595 @@ impl OrderCollection
     /// ```
     pub async fn update(
         &self,
-        req_id: String,
+        id: ObjectId,
         content: OrderUpdateRequest,
-    ) -> Result<UpdateResult, OrderCollectionError>
+    ) -> Result<(Order, Returned), OrderCollectionError>
     {
         info!("Updating order...");
 
-        let id = ObjectId::from_str(&req_id).unwrap();
+        let mut order = self.get(id).await?;
 
-        let filter = doc! { "_id": id };
+        if order.status == content.status
+        {
+            return Err(OrderCollectionError::OrderNotModified);
+        }
 
-        let status_as_string = bson::to_bson(&content.status).unwrap();
-        let status = status_as_string.as_str().unwrap();
+        if !order.status.can_transition_to(content.status)
+        {
+            return Err(OrderCollectionError::InvalidStatusTransition {
+                from: order.status,
+                to: content.status,
+                allowed: order.status.next_statuses().to_vec(),
+            });
+        }
 
-        let update = doc! { "$set": { "status": status } };
+        let now = chrono::Utc::now();
 
-        let result = self.collection_order.update_one(filter, update, None).await;
+        let mut items = order.products.clone();
 
-        match result
+        let returned = match content.status
         {
-            Ok(result) => match result.modified_count
+            OrderStatus::Cancelled => Returned::cancel_unserved(&mut items, now),
+            status =>
             {
-                1 => Ok(result),
-                _ => Err(OrderCollectionError::OrderNotModified),
+                items.iter_mut().for_each(|item| item.follow(status, now));
+                Returned::default()
             },
+        };
+
+        let update = doc! {
+            "status": storage::to_bson(&content.status).unwrap(),
+            "products": storage::to_bson(&items).unwrap(),
+        };
+
+        self.apply_update(&order, update, now).await?;
+
+        order.set_status(content.status);
+        order.products = items;
+        order.updated_at = now;
+
+        Ok((order, returned))
+    }
+
+    /// Update a single line item of an order
+    ///
+    /// The status of the order is derived again from its items afterwards. Returns
+    /// what the item gives back if it was cancelled.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - ObjectId
+    /// * `index` - Position of the item in the order
+    /// * `content` - OrderItemUpdateRequest
+    ///
+    pub async fn update_item(
+        &self,
+        id: ObjectId,
+        index: usize,
+        content: OrderItemUpdateRequest,
+    ) -> Result<(Order, Returned), OrderCollectionError>
+    {
+        info!("Updating order item...");
+
+        let order = self.get(id).await?;
+
+        self.change_item(order, index, content).await
+    }
+
+    /// Moves a line item on to its next preparation step, as a display bump does
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - ObjectId
+    /// * `index` - Position of the item in the order
+    ///
+    pub async fn bump_item(
+        &self,
+        id: ObjectId,
+        index: usize,
+    ) -> Result<Order, OrderCollectionError>
+    {
+        info!("Bumping order item...");
+
+        let order = self.get(id).await?;
+
+        let status = match order.products.get(index)
+        {
+            Some(item) => item.status.bumped(),
+            None => return Err(OrderCollectionError::ItemNotFound),
+        };
+
+        let content = OrderItemUpdateRequest {
+            status: Some(status.ok_or(OrderCollectionError::OrderNotModified)?),
+            assignee: None,
+        };
+
+        // bumps only move items forward, so no stock comes back
+        let (order, _) = self.change_item(order, index, content).await?;
+
+        Ok(order)
+    }
+
+    /// Sends the ready items of an order back to the kitchen
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - ObjectId
+    ///
+    pub async fn recall(&self, id: ObjectId) -> Result<Order, OrderCollectionError>
+    {
+        info!("Recalling order...");
+
+        let mut order = self.get(id).await?;
+
+        let now = chrono::Utc::now();
+
+        let mut recalled = false;
+
+        for item in order.products.iter_mut().filter(|item| item.status == ItemStatus::Ready)
+        {
+            item.status = ItemStatus::InPreparation;
+            item.started_at.get_or_insert(now);
+            item.ready_at = None;
+            recalled = true;
+        }
+
+        let status = order.status.derive_from(&order.products);
+
+        if !recalled || status != OrderStatus::InPreparation
+        {
+            return Err(OrderCollectionError::InvalidStatusTransition {
+                from: order.status,
+                to: OrderStatus::InPreparation,
+                allowed: order.status.next_statuses().to_vec(),
+            });
+        }
+
+        let update = doc! {
+            "status": storage::to_bson(&status).unwrap(),
+            "products": storage::to_bson(&order.products).unwrap(),
+        };
+
+        self.apply_update(&order, update, now).await?;
+
+        order.set_status(status);
+        order.updated_at = now;
+
+        Ok(order)
+    }
+
+    /// Sets or removes the note of an order, or of one of its lines when `index`
+    /// is given, recording the change in the order's `note_history`
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - ObjectId
+    /// * `index` - Position of the item in the order, if any
+    /// * `content` - NoteUpdateRequest
+    ///
+    pub async fn set_note(
+        &self,
+        id: ObjectId,
+        index: Option<usize>,
+        content: NoteUpdateRequest,
+    ) -> Result<Order, OrderCollectionError>
+    {
+        info!("Updating order note...");
+
+        let mut order = self.get(id).await?;
+
+        let now = chrono::Utc::now();
+
+        let note = match index
+        {
+            Some(index) => match order.products.get_mut(index)
+            {
+                Some(item) => &mut item.note,
+                None => return Err(OrderCollectionError::ItemNotFound),
+            },
+            None => &mut order.note,
+        };
+
+        if *note == content.note
+        {
+            return Err(OrderCollectionError::OrderNotModified);
+        }
+
+        let previous = std::mem::replace(note, content.note.clone());
+
+        order.note_history.push(NoteChange {
+            index,
+            previous,
+            note: content.note,
+            editor: content.editor,
+            changed_at: now,
+        });
+
+        order.allergy = order.has_allergy_note();
+
+        let update = doc! {
+            "note": storage::to_bson(&order.note).unwrap(),
+            "products": storage::to_bson(&order.products).unwrap(),
+            "allergy": order.allergy,
+            "note_history": storage::to_bson(&order.note_history).unwrap(),
+        };
+
+        self.apply_update(&order, update, now).await?;
+
+        order.updated_at = now;
+
+        Ok(order)
+    }
+
+    /// Applies an item update to an order that was just read
+    async fn change_item(
+        &self,
+        mut order: Order,
+        index: usize,
+        content: OrderItemUpdateRequest,
+    ) -> Result<(Order, Returned), OrderCollectionError>
+    {
+        let now = chrono::Utc::now();
+
+        let item = match order.products.get_mut(index)
+        {
+            Some(item) => item,
+            None => return Err(OrderCollectionError::ItemNotFound),
+        };
+
+        let mut modified = false;
+
+        let mut returned = Returned::default();
+
+        if let Some(status) = content.status.filter(|status| *status != item.status)
+        {
+            if !item.status.can_transition_to(status)
+            {
+                return Err(OrderCollectionError::InvalidItemStatusTransition {
+                    from: item.status,
+                    to: status,
+                    allowed: item.status.next_statuses().to_vec(),
+                });
+            }
+
+            item.set_status(status, now);
+            returned.add(item);
+            modified = true;
+        }
+
+        if content.assignee.is_some() && content.assignee != item.assignee
+        {
+            item.assignee = content.assignee;
+            modified = true;
+        }
+
+        if !modified
+        {
+            return Err(OrderCollectionError::OrderNotModified);
+        }
+
+        let status = order.status.derive_from(&order.products);
+
+        let update = doc! {
+            "status": storage::to_bson(&status).unwrap(),
+            "products": storage::to_bson(&order.products).unwrap(),
+        };
+
+        self.apply_update(&order, update, now).await?;
+
+        order.set_status(status);
+        order.updated_at = now;
+
+        Ok((order, returned))
+    }
+
+    /// Writes `update` to the order, bumping `updated_at`, unless the order was
+    /// changed by someone else since it was read
+    async fn apply_update(
+        &self,
+        order: &Order,
+        mut update: Document,
+        now: DateTime<Utc>,
+    ) -> Result<UpdateOutcome, OrderCollectionError>
+    {
+        let id = match order.id
+        {
+            Some(id) => id,
+            None => return Err(OrderCollectionError::OrderNotFound),
+        };
+
+        let expected = doc! { "updated_at": storage::to_bson(&order.updated_at).unwrap() };
+
+        update.insert("updated_at", storage::to_bson(&now).unwrap());
+
+        let result = self.collection_order.update_if(&id, expected, update).await;
+
+        match result
+        {
+            Ok(result) if result.modified => Ok(result),
+            Ok(_) => Err(OrderCollectionError::Conflict),
             Err(error) => Err(OrderCollectionError::CustomError(error.to_string())),
         }
     }
 
-    /// Delete a single order
+    /// Rewrites totals stored before `Money` as exact amounts, returning how many changed
+    ///
+    /// Old totals are plain numbers in `CURRENCY`; running it again changes nothing.
+    pub async fn migrate_prices(&self) -> Result<usize, OrderCollectionError>
+    {
+        info!("Migrating order totals...");
+
+        let to_custom_error =
+            |error: storage::StorageError| OrderCollectionError::CustomError(error.to_string());
+
+        let orders = self.collection_order.list(0, 0).await.map_err(to_custom_error)?;
+
+        let mut migrated = 0;
+
+        for order in orders
+        {
+            let id = match order.id
+            {
+                Some(id) => id,
+                None => continue,
+            };
+
+            let total_price = storage::to_bson(&order.total_price).map_err(to_custom_error)?;
+
+            let result = self
+                .collection_order
+                .update(&id, doc! { "total_price": total_price })
+                .await
+                .map_err(to_custom_error)?;
+
+            if result.modified
+            {
+                migrated += 1;
+            }
+        }
+
+        Ok(migrated)
+    }
+
+    /// Rewrites creation times stored with fewer than nine fraction digits, which do
+    /// not sort like the times they give, returning how many changed
+    ///
+    /// Running it again changes nothing.
+    pub async fn migrate_times(&self) -> Result<usize, OrderCollectionError>
+    {
+        info!("Migrating order times...");
+
+        let to_custom_error =
+            |error: storage::StorageError| OrderCollectionError::CustomError(error.to_string());
+
+        let orders = self.collection_order.list(0, 0).await.map_err(to_custom_error)?;
+
+        let mut migrated = 0;
+
+        for order in orders
+        {
+            let id = match order.id
+            {
+                Some(id) => id,
+                None => continue,
+            };
+
+            let created_at = stored_time::format(&order.created_at);
+
+            let result = self
+                .collection_order
+                .update(&id, doc! { "created_at": created_at })
+                .await
+                .map_err(to_custom_error)?;
+
+            if result.modified
+            {
+                migrated += 1;
+            }
+        }
+
+        Ok(migrated)
+    }
+
+    /// Delete a single order, returning it as it was
+    ///
+    /// Lines that were not served are given up as if the order was cancelled, so
+    /// what they give back is returned as well.
     /// 
     /// # Arguments
     /// 
-    /// * `req_id` - ObjectId
+    /// * `id` - ObjectId
     /// 
     /// ```
     /// # Examples
     /// 
     /// ```
-    pub async fn delete(&self, req_id: String) -> Result<DeleteResult, OrderCollectionError>
+    pub async fn delete(
+        &self,
+        id: ObjectId,
+    ) -> Result<(Order, Returned), OrderCollectionError>
     {
         info!("Deleting order...");
 
-        let id = ObjectId::from_str(&req_id).unwrap();
+        let order = self.get(id).await?;
 
-        let filter = doc! { "_id": id };
+        let returned = Returned::cancel_unserved(&mut order.products.clone(), chrono::Utc::now());
 
-        let result = self.collection_order.delete_one(filter, None).await;
+        let result = self.collection_order.delete(&id).await;
 
         match result
         {
-            Ok(result) => match result.deleted_count
-            {
-                1 => Ok(result),
-                _ => Err(OrderCollectionError::OrderNotFound),
-            },
+            Ok(true) => Ok((order, returned)),
+            Ok(false) => Err(OrderCollectionError::OrderNotFound),
+            Err(error) => Err(OrderCollectionError::CustomError(error.to_string())),
+        }
+    }
+
+    /// Draws the next ticket number of the given numbering period
+    async fn next_order_number(&self, order_period: &str) -> Result<i32, OrderCollectionError>
+    {
+        let key = format!("order_id:{}", order_period);
+
+        match self.counters.next(&key).await
+        {
+            Ok(number) => Ok(number as i32),
             Err(error) => Err(OrderCollectionError::CustomError(error.to_string())),
         }
     }
 }
+
+#[cfg(test)]
+mod tests
+{
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::database::tests::{memory, place, product};
+
+    #[actix_web::test]
+    async fn numbers_orders_one_after_another()
+    {
+        let database = memory().await;
+        let orders = database.orders().await;
+
+        let soup = json!({ "name": "Soup", "price": "4.50", "kind": "Food" });
+        let soup = product(&database, soup).await.id.unwrap();
+
+        let mut numbers = Vec::new();
+
+        for _ in 0..3
+        {
+            numbers.push(place(&database, &[soup], 1).await.unwrap().order_id);
+        }
+
+        assert_eq!(numbers, [1, 2, 3]);
+
+        let third = orders.get_by_number(3).await.unwrap();
+
+        assert_eq!(third.order_period, OrderNumbering::from_env().period(third.created_at));
+        assert!(orders.get_by_number(4).await.is_err());
+    }
+
+    #[actix_web::test]
+    async fn rejects_transitions_outside_the_table()
+    {
+        let database = memory().await;
+        let orders = database.orders().await;
+
+        let soup = json!({ "name": "Soup", "price": "4.50", "kind": "Food" });
+        let soup = product(&database, soup).await.id.unwrap();
+        let order = place(&database, &[soup], 1).await.unwrap();
+        let id = order.id.unwrap();
+
+        let update = |status| orders.update(id, OrderUpdateRequest { status });
+
+        match update(OrderStatus::Served).await
+        {
+            Err(OrderCollectionError::InvalidStatusTransition { from, to, allowed }) =>
+            {
+                assert_eq!((from, to), (OrderStatus::Received, OrderStatus::Served));
+                assert_eq!(allowed, [OrderStatus::Accepted, OrderStatus::Cancelled]);
+            },
+            other => panic!("expected an invalid transition, got {:?}", other),
+        }
+
+        assert!(matches!(
+            update(OrderStatus::Received).await,
+            Err(OrderCollectionError::OrderNotModified)
+        ));
+
+        let (accepted, _) = update(OrderStatus::Accepted).await.unwrap();
+
+        assert_eq!(accepted.status, OrderStatus::Accepted);
+        assert!(accepted.updated_at > order.updated_at);
+
+        let (cancelled, _) = update(OrderStatus::Cancelled).await.unwrap();
+
+        assert_eq!(cancelled.products[0].status, ItemStatus::Cancelled);
+        assert!(update(OrderStatus::Accepted).await.is_err());
+    }
+
+    #[actix_web::test]
+    async fn keeps_a_history_of_note_changes()
+    {
+        let database = memory().await;
+        let orders = database.orders().await;
+
+        let soup = json!({ "name": "Soup", "price": "4.50", "kind": "Food" });
+        let soup = product(&database, soup).await.id.unwrap();
+        let id = place(&database, &[soup], 1).await.unwrap().id.unwrap();
+
+        let note = |content: Value, index| {
+            let content = serde_json::from_value(content).unwrap();
+            orders.set_note(id, index, content)
+        };
+
+        let window = json!({ "note": { "text": "Table by the window" }, "editor": "Sam" });
+        let nuts = json!({ "note": { "text": "Nut allergy", "allergy": true } });
+
+        let order = note(window.clone(), None).await.unwrap();
+
+        assert!(!order.allergy);
+
+        let order = note(nuts.clone(), Some(0)).await.unwrap();
+
+        assert!(order.allergy);
+        assert_eq!(order.products[0].note.as_ref().unwrap().text, "Nut allergy");
+
+        assert!(matches!(
+            note(nuts, Some(0)).await,
+            Err(OrderCollectionError::OrderNotModified)
+        ));
+        assert!(matches!(
+            note(window, Some(1)).await,
+            Err(OrderCollectionError::ItemNotFound)
+        ));
+
+        let order = note(json!({ "note": null }), Some(0)).await.unwrap();
+
+        assert!(!order.allergy);
+
+        let history = orders.get(id).await.unwrap().note_history;
+        let changes: Vec<(Option<usize>, bool, bool)> = history
+            .iter()
+            .map(|change| (change.index, change.previous.is_some(), change.note.is_some()))
+            .collect();
+
+        assert_eq!(changes, [(None, false, true), (Some(0), false, true), (Some(0), true, false)]);
+        assert_eq!(history[0].editor.as_deref(), Some("Sam"));
+        assert!(history[2].previous.as_ref().unwrap().allergy);
+    }
+
+    #[actix_web::test]
+    async fn takes_orders_from_what_remains_and_gives_cancelled_lines_back()
+    {
+        let database = memory().await;
+        let (orders, products) = (database.orders().await, database.products().await);
+
+        let pie = json!({ "name": "Pie", "price": "3.00", "kind": "Food" });
+        let pie = product(&database, pie).await.id.unwrap();
+        let remaining = || async { products.get(pie).await.unwrap().remaining };
+
+        let content = serde_json::from_value(json!({ "remaining": 3 })).unwrap();
+        products.set_availability(pie, content).await.unwrap();
+
+        let order = place(&database, &[pie], 2).await.unwrap();
+
+        assert_eq!(remaining().await, Some(1));
+
+        let rejected = place(&database, &[pie], 2).await;
+
+        assert!(matches!(rejected, Err(OrderCollectionError::ProductUnavailable(_))));
+        assert_eq!(remaining().await, Some(1));
+
+        let content = OrderUpdateRequest { status: OrderStatus::Cancelled };
+        let (_, returned) = orders.update(order.id.unwrap(), content).await.unwrap();
+
+        assert_eq!(returned.products, [(pie, 2)]);
+
+        // deleting the cancelled order has nothing left to give back
+        let (_, returned) = orders.delete(order.id.unwrap()).await.unwrap();
+
+        assert!(returned.is_empty());
+    }
+}
\ No newline at end of file
@@ -1,6 +1,8 @@
 pub mod collection;
 pub mod model;
+pub mod numbering;
 pub mod service;
+pub mod socket;
 pub mod stream;
 
 use actix_web::{web, App, HttpServer, Scope};
@@ -14,10 +16,30 @@ pub fn config(config: &mut web::ServiceConfig)
             .route(web::post().to(service::create))
             .route(web::get().to(service::list))
         )
+        .service(
+            web::resource("/by-number/{number}")
+            .route(web::get().to(service::get_by_number))
+        )
         .service(
             web::resource("/events/update")
             .route(web::get().to(stream::order_update))
         )
+        .service(
+            web::resource("/events/ws")
+            .route(web::get().to(socket::order_socket))
+        )
+        .service(
+            web::resource("/{id}/items/{index}/note")
+            .route(web::put().to(service::set_item_note))
+        )
+        .service(
+            web::resource("/{id}/items/{index}")
+            .route(web::put().to(service::update_item))
+        )
+        .service(
+            web::resource("/{id}/note")
+            .route(web::put().to(service::set_note))
+        )
         .service(
             web::resource("/{id}")
             .route(web::get().to(service::get))
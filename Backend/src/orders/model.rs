@@ -1,30 +1,561 @@
-use chrono::{Utc, DateTime};
+use chrono::{Utc, DateTime, Duration};
 use mongodb::bson::oid::ObjectId;
 use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+use validator::Validate;
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+use crate::broadcast;
+use crate::common_model::{self, stored_time, DateOrTime};
+use crate::ingredients::model::{self, IngredientAmount};
+use crate::money::Money;
+use crate::products::model::{Allergen, ModifierGroup, Product, ProductKind};
+use crate::storage::{self, Entity, Filter, Sort, SortOrder};
+use crate::validation;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Validate)]
 pub struct ProductView {
+    #[validate(custom = "validation::object_id")]
     pub id: String,
+    #[validate(range(min = 1, message = "must be at least 1"))]
     pub quantity: i32,
+    #[serde(default)]
+    #[validate]
+    pub modifiers: Vec<ModifierChoice>,
+    #[validate]
+    pub note: Option<Note>,
+}
+
+/// A free-text note on an order or one of its lines, e.g. "table by the window"
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Validate)]
+pub struct Note {
+    #[validate(
+        length(max = 500, message = "must be at most 500 characters"),
+        custom = "validation::not_blank"
+    )]
+    pub text: String,
+    /// Marks notes about allergies, which displays should not miss
+    #[serde(default)]
+    pub allergy: bool,
 }
 
+/// A note of an order that was added, changed or removed after it was placed
 #[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct NoteChange {
+    /// The line whose note changed, `None` for the note of the order itself
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub index: Option<usize>,
+    pub previous: Option<Note>,
+    pub note: Option<Note>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub editor: Option<String>,
+    pub changed_at: DateTime<Utc>,
+}
+
+/// An option picked from one of the modifier groups of a product, by name
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Validate)]
+pub struct ModifierChoice {
+    #[validate(custom = "validation::not_blank")]
+    pub group: String,
+    #[validate(custom = "validation::not_blank")]
+    pub option: String,
+}
+
+/// A modifier of an order line, with its price when the order was placed
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct OrderModifier {
+    pub group: String,
+    pub option: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub price: Option<Money>,
+}
+
+/// Checks `choices` against the modifier groups of `product`, explaining what is
+/// wrong when a choice is unknown or a group has too few or too many
+pub fn choose_modifiers(
+    product: &Product,
+    choices: &[ModifierChoice],
+) -> Result<Vec<OrderModifier>, String>
+{
+    let mut chosen = Vec::new();
+
+    for (index, choice) in choices.iter().enumerate()
+    {
+        if choices[..index].contains(choice)
+        {
+            return Err(format!("{} {} is chosen twice.", choice.group, choice.option));
+        }
+
+        let option = product
+            .modifiers
+            .iter()
+            .filter(|group| group.name == choice.group)
+            .flat_map(|group| &group.options)
+            .find(|option| option.name == choice.option);
+
+        match option
+        {
+            Some(option) => chosen.push(OrderModifier {
+                group: choice.group.clone(),
+                option: option.name.clone(),
+                price: option.price,
+            }),
+            None =>
+            {
+                return Err(format!(
+                    "{} has no option {} {}.",
+                    product.name, choice.group, choice.option
+                ))
+            },
+        }
+    }
+
+    for group in &product.modifiers
+    {
+        let count = chosen.iter().filter(|modifier| modifier.group == group.name).count() as u32;
+
+        let too_few = (group.required || count > 0) && count < group.min_choices();
+        let too_many = group.max.is_some_and(|max| count > max);
+
+        if too_few || too_many
+        {
+            return Err(format!("{} takes {} of {}.", product.name, limits(group), group.name));
+        }
+    }
+
+    Ok(chosen)
+}
+
+fn limits(group: &ModifierGroup) -> String
+{
+    match (group.min_choices(), group.max)
+    {
+        (min, Some(max)) if min == max => format!("exactly {}", min),
+        (0, Some(max)) => format!("at most {}", max),
+        (min, Some(max)) => format!("{} to {}", min, max),
+        (min, None) => format!("at least {}", min),
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+pub enum ItemStatus {
+    #[default]
+    Queued = 0,
+    InPreparation,
+    Ready,
+    Served,
+    Cancelled,
+}
+
+impl ItemStatus
+{
+    /// Statuses a line item in this status may move to
+    pub fn next_statuses(&self) -> &'static [ItemStatus]
+    {
+        use ItemStatus::*;
+
+        match self
+        {
+            Queued => &[InPreparation, Ready, Cancelled],
+            InPreparation => &[Ready, Cancelled],
+            // a ready item can be sent back to the kitchen, e.g. when it got cold
+            Ready => &[Served, InPreparation, Cancelled],
+            Served | Cancelled => &[],
+        }
+    }
+
+    pub fn can_transition_to(&self, next: ItemStatus) -> bool
+    {
+        self.next_statuses().contains(&next)
+    }
+
+    /// The status a display bump moves a line item on to, if any
+    pub fn bumped(&self) -> Option<ItemStatus>
+    {
+        match self
+        {
+            ItemStatus::Queued => Some(ItemStatus::InPreparation),
+            ItemStatus::InPreparation => Some(ItemStatus::Ready),
+            ItemStatus::Ready => Some(ItemStatus::Served),
+            ItemStatus::Served | ItemStatus::Cancelled => None,
+        }
+    }
+}
+
+/// A product line of an order, prepared and tracked on its own
+///
+/// The product is copied into the line when the order is placed, so later changes
+/// to it do not rewrite the order. Orders placed before that have no copy.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct OrderItem {
+    pub id: String,
+    pub quantity: i32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kind: Option<ProductKind>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unit_price: Option<Money>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allergens: Vec<Allergen>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub modifiers: Vec<OrderModifier>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<Note>,
+    /// The quantity times the unit price and the price of each modifier
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub line_total: Option<Money>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub station: Option<String>,
+    #[serde(default)]
+    pub status: ItemStatus,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub assignee: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub started_at: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ready_at: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub served_at: Option<DateTime<Utc>>,
+    /// What the line took from stock, until it is given back on cancellation
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub stock: Vec<IngredientAmount>,
+}
+
+impl OrderItem
+{
+    /// A line of `view.quantity` times `product` with its `modifiers`, without a
+    /// total if it overflows or the prices are in different currencies
+    pub fn new(
+        view: ProductView,
+        product: &Product,
+        modifiers: Vec<OrderModifier>,
+        station: Option<String>,
+    ) -> Self
+    {
+        let unit_total = modifiers
+            .iter()
+            .filter_map(|modifier| modifier.price)
+            .try_fold(product.price, |total, price| total.checked_add(price));
+
+        let stock = product.recipe.iter().flat_map(|recipe| &recipe.ingredients).map(|amount| {
+            IngredientAmount {
+                ingredient: amount.ingredient,
+                quantity: model::round(amount.quantity * view.quantity as f64),
+            }
+        });
+
+        OrderItem {
+            stock: IngredientAmount::combine(stock),
+            line_total: unit_total.and_then(|total| total.checked_mul(view.quantity as i64)),
+            modifiers,
+            note: view.note,
+            id: view.id,
+            quantity: view.quantity,
+            name: Some(product.name.clone()),
+            kind: Some(product.kind.clone()),
+            unit_price: Some(product.price),
+            allergens: product.allergens.clone(),
+            station,
+            status: ItemStatus::Queued,
+            assignee: None,
+            started_at: None,
+            ready_at: None,
+            served_at: None,
+        }
+    }
+
+    /// Moves the item to `status`, stamping the time it reached it
+    pub fn set_status(&mut self, status: ItemStatus, time: DateTime<Utc>)
+    {
+        match status
+        {
+            ItemStatus::InPreparation => self.started_at = Some(time),
+            ItemStatus::Ready =>
+            {
+                self.started_at.get_or_insert(time);
+                self.ready_at = Some(time);
+            },
+            ItemStatus::Served => self.served_at = Some(time),
+            ItemStatus::Queued | ItemStatus::Cancelled => (),
+        }
+
+        self.status = status;
+    }
+
+    /// Moves the item along with its order, when the order is set to `Ready` or
+    /// `Served` by hand
+    pub fn follow(&mut self, status: OrderStatus, time: DateTime<Utc>)
+    {
+        let steps: &[ItemStatus] = match status
+        {
+            OrderStatus::Ready => &[ItemStatus::Ready],
+            OrderStatus::Served => &[ItemStatus::Ready, ItemStatus::Served],
+            _ => &[],
+        };
+
+        for step in steps
+        {
+            if self.status.can_transition_to(*step)
+            {
+                self.set_status(*step, time);
+            }
+        }
+    }
+
+    /// The stock to give back for a cancelled line, which is handed over only once
+    pub fn returned_stock(&mut self) -> Vec<IngredientAmount>
+    {
+        match self.status
+        {
+            ItemStatus::Cancelled => std::mem::take(&mut self.stock),
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// What cancelled lines give back: the stock they took, and the quantity of each
+/// product they took from what remains of it
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Returned
+{
+    pub stock: Vec<IngredientAmount>,
+    pub products: Vec<(ObjectId, u32)>,
+}
+
+impl Returned
+{
+    /// Cancels the lines that have not been served yet, collecting what they give back
+    pub fn cancel_unserved(items: &mut [OrderItem], time: DateTime<Utc>) -> Self
+    {
+        let mut returned = Returned::default();
+
+        for item in items
+            .iter_mut()
+            .filter(|item| item.status.can_transition_to(ItemStatus::Cancelled))
+        {
+            item.set_status(ItemStatus::Cancelled, time);
+            returned.add(item);
+        }
+
+        returned
+    }
+
+    /// Adds what a line that was just cancelled gives back
+    pub fn add(&mut self, item: &mut OrderItem)
+    {
+        if item.status != ItemStatus::Cancelled
+        {
+            return;
+        }
+
+        let stock = std::mem::take(&mut self.stock);
+        self.stock = IngredientAmount::combine(stock.into_iter().chain(item.returned_stock()));
+
+        if let Ok(id) = ObjectId::parse_str(&item.id)
+        {
+            let quantity = item.quantity as u32;
+
+            match self.products.iter_mut().find(|(known, _)| *known == id)
+            {
+                Some((_, known)) => *known += quantity,
+                None => self.products.push((id, quantity)),
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool
+    {
+        self.stock.is_empty() && self.products.is_empty()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
 pub enum OrderStatus {
-    Pending = 0,
+    #[serde(alias = "Pending")]
+    Received = 0,
+    Accepted,
+    InPreparation,
+    Ready,
+    Served,
     Completed,
     Cancelled,
 }
 
+impl OrderStatus
+{
+    /// Statuses an order in this status may move to
+    pub fn next_statuses(&self) -> &'static [OrderStatus]
+    {
+        use OrderStatus::*;
+
+        match self
+        {
+            Received => &[Accepted, Cancelled],
+            Accepted => &[InPreparation, Cancelled],
+            InPreparation => &[Ready, Cancelled],
+            Ready => &[Served, Cancelled],
+            Served => &[Completed],
+            Completed | Cancelled => &[],
+        }
+    }
+
+    pub fn can_transition_to(&self, next: OrderStatus) -> bool
+    {
+        self.next_statuses().contains(&next)
+    }
+
+    /// Whether `next` follows this status through one or more transitions
+    pub fn can_reach(&self, next: OrderStatus) -> bool
+    {
+        self.next_statuses().iter().any(|status| *status == next || status.can_reach(next))
+    }
+
+    /// Whether the progress of its items may move an order in this status to `next`
+    ///
+    /// Items move on their own, so the order may skip steps, e.g. from `Received`
+    /// straight to `Ready` when only ready-made products were ordered. The one way
+    /// back is from `Ready` to `InPreparation`, when items are sent back to the kitchen.
+    pub fn can_derive(&self, next: OrderStatus) -> bool
+    {
+        *self == next
+            || self.can_reach(next)
+            || (*self == OrderStatus::Ready && next == OrderStatus::InPreparation)
+    }
+
+    /// Derives the status of an order in this status from the progress of its items
+    ///
+    /// Finished orders keep their status, and orders none of whose items have
+    /// been started by the kitchen keep theirs too, as do orders the derived
+    /// status cannot be reached from by `can_derive`.
+    pub fn derive_from(&self, items: &[OrderItem]) -> OrderStatus
+    {
+        let derived = self.derive(items);
+
+        match self.can_derive(derived)
+        {
+            true => derived,
+            false => *self,
+        }
+    }
+
+    fn derive(&self, items: &[OrderItem]) -> OrderStatus
+    {
+        if let OrderStatus::Completed | OrderStatus::Cancelled = self
+        {
+            return *self;
+        }
+
+        let active: Vec<&OrderItem> = items
+            .iter()
+            .filter(|item| item.status != ItemStatus::Cancelled)
+            .collect();
+
+        if active.is_empty()
+        {
+            OrderStatus::Cancelled
+        }
+        else if active.iter().all(|item| item.status == ItemStatus::Served)
+        {
+            OrderStatus::Served
+        }
+        else if active
+            .iter()
+            .all(|item| matches!(item.status, ItemStatus::Ready | ItemStatus::Served))
+        {
+            OrderStatus::Ready
+        }
+        else if active.iter().any(|item| item.started_at.is_some())
+        {
+            OrderStatus::InPreparation
+        }
+        else
+        {
+            *self
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Order {
     #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
     pub id: Option<ObjectId>,
     pub order_id: i32,
-    pub products: Vec<ProductView>,
-    pub total_price: f32,
+    #[serde(default)]
+    pub order_period: String,
+    pub products: Vec<OrderItem>,
+    pub total_price: Money,
     pub status: OrderStatus,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<Note>,
+    /// Whether the order or one of its lines has an allergy note
+    #[serde(default)]
+    pub allergy: bool,
+    /// The allergens of the products on the order, as they were when it was placed
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allergens: Vec<Allergen>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub note_history: Vec<NoteChange>,
+    #[serde(with = "common_model::stored_time")]
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
+    /// The status the order left in the change that returned it, so events about
+    /// the change reach subscribers of either status; never stored
+    #[serde(skip)]
+    pub previous_status: Option<OrderStatus>,
+}
+
+impl Order
+{
+    /// Moves the order to `status`, remembering the status it leaves
+    pub fn set_status(&mut self, status: OrderStatus)
+    {
+        if status != self.status
+        {
+            self.previous_status = Some(self.status);
+        }
+
+        self.status = status;
+    }
+
+    /// Whether any note of the order is an allergy note, to keep `allergy` up to date
+    pub fn has_allergy_note(&self) -> bool
+    {
+        self.note
+            .iter()
+            .chain(self.products.iter().filter_map(|item| item.note.as_ref()))
+            .any(|note| note.allergy)
+    }
+
+    /// Every allergen found on a line of the order, each once
+    pub fn line_allergens(&self) -> Vec<Allergen>
+    {
+        let mut allergens: Vec<Allergen> =
+            self.products.iter().flat_map(|item| item.allergens.iter().copied()).collect();
+
+        allergens.sort_unstable();
+        allergens.dedup();
+
+        allergens
+    }
+
+    /// What the lines of the order take from stock, by ingredient
+    pub fn stock(&self) -> Vec<IngredientAmount>
+    {
+        IngredientAmount::combine(self.products.iter().flat_map(|item| item.stock.clone()))
+    }
+}
+
+impl Entity for Order
+{
+    fn id(&self) -> Option<ObjectId>
+    {
+        self.id
+    }
+
+    fn set_id(&mut self, id: ObjectId)
+    {
+        self.id = Some(id);
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -32,16 +563,666 @@ pub struct OrderListQuery
 {
     pub offset: Option<u64>,
     pub limit: Option<i64>,
+    /// Only orders in one of these states, e.g. `Cancelled` or `Served,Completed`
+    #[serde(default, deserialize_with = "common_model::comma_separated")]
+    pub status: Vec<OrderStatus>,
+    /// Only orders placed at or after this time, or on or after this day
+    pub from: Option<DateOrTime>,
+    /// Only orders placed before this time, or on or before this day
+    pub to: Option<DateOrTime>,
+    /// Only orders with a line of this product
+    pub product: Option<ObjectId>,
+    /// Only orders with this ticket number, which repeats once numbering starts again
+    pub number: Option<i32>,
+    /// Only orders with (`true`) or without (`false`) an allergy note
+    pub allergy: Option<bool>,
+    /// Newest first unless `asc`
+    pub order: Option<SortOrder>,
+}
+
+impl OrderListQuery
+{
+    /// The storage filter selecting the orders asked for
+    pub fn filter(&self) -> Filter
+    {
+        let mut filter = Filter::All;
+
+        if !self.status.is_empty()
+        {
+            let statuses = self.status.iter().filter_map(|status| storage::to_bson(status).ok());
+
+            filter = filter.and(Filter::one_of("status", statuses.collect()));
+        }
+
+        if let Some(from) = self.from
+        {
+            filter = filter.and(Filter::at_least("created_at", stored_time::format(&from.start())));
+        }
+
+        // times are kept to the nanosecond, so the one before the end is the last included
+        if let Some(to) = self.to
+        {
+            let end = to.end();
+            let last = end.checked_sub_signed(Duration::nanoseconds(1)).unwrap_or(end);
+
+            filter = filter.and(Filter::at_most("created_at", stored_time::format(&last)));
+        }
+
+        if let Some(product) = self.product
+        {
+            filter = filter.and(Filter::eq("products.id", product.to_hex()));
+        }
+
+        if let Some(number) = self.number
+        {
+            filter = filter.and(Filter::eq("order_id", number));
+        }
+
+        if let Some(allergy) = self.allergy
+        {
+            filter = filter.and(Filter::eq("allergy", allergy));
+        }
+
+        filter
+    }
+
+    /// The order asked for, by when orders were placed
+    pub fn sort(&self) -> Sort
+    {
+        Sort::new("created_at", self.order.unwrap_or(SortOrder::Desc))
+    }
 }
 
+/// Narrows an event stream down to the orders a display cares about
 #[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct OrderCreateRequest
+pub struct OrderEventsQuery
 {
-    pub products: Vec<ProductView>
+    pub station: Option<String>,
+    pub status: Option<OrderStatus>,
+    pub order: Option<String>,
+    /// Only orders with (`true`) or without (`false`) an allergy note
+    pub allergy: Option<bool>,
+    /// Also sends `item_recipes` events, with the recipes of new lines
+    pub recipes: Option<bool>,
+    /// Resumes after this event, for clients that cannot send `Last-Event-ID`
+    pub last_event_id: Option<u64>,
+}
+
+impl OrderEventsQuery
+{
+    /// The tag filter of a broadcaster client
+    pub fn filter(self) -> HashMap<String, String>
+    {
+        let mut filter = HashMap::new();
+
+        if let Some(station) = self.station
+        {
+            filter.insert("station".to_string(), station);
+        }
+
+        if let Some(status) = self.status
+        {
+            filter.insert("status".to_string(), format!("{:?}", status));
+        }
+
+        if let Some(order) = self.order
+        {
+            filter.insert("order".to_string(), order);
+        }
+
+        if let Some(allergy) = self.allergy
+        {
+            filter.insert("allergy".to_string(), allergy.to_string());
+        }
+
+        if self.recipes == Some(true)
+        {
+            filter.insert(broadcast::OPT_IN.to_string(), "item_recipes".to_string());
+        }
+
+        filter
+    }
 }
 
+/// A command sent by a kitchen display over the order socket
 #[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum DisplayCommand
+{
+    /// Moves a line item on to its next preparation step
+    BumpItem { order: String, index: usize },
+    /// Sends the ready items of an order back to the kitchen
+    RecallOrder { order: String },
+    /// Confirms a received order was seen by the kitchen
+    Acknowledge { order: String },
+}
+
+impl DisplayCommand
+{
+    /// The id of the order the command applies to
+    pub fn order(&self) -> &str
+    {
+        match self
+        {
+            DisplayCommand::BumpItem { order, .. }
+            | DisplayCommand::RecallOrder { order }
+            | DisplayCommand::Acknowledge { order } => order,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Validate)]
+pub struct OrderCreateRequest
+{
+    #[validate(length(min = 1, message = "must contain at least one product"))]
+    #[validate]
+    pub products: Vec<ProductView>,
+    #[validate]
+    pub note: Option<Note>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Validate)]
 pub struct OrderUpdateRequest
 {
     pub status: OrderStatus,
+}
+
+/// Sets or, with `null`, removes the note of an order or one of its lines
+#[derive(Debug, Serialize, Deserialize, Clone, Validate)]
+pub struct NoteUpdateRequest
+{
+    #[validate]
+    pub note: Option<Note>,
+    /// Who changed the note, kept in the order's `note_history`
+    #[validate(
+        length(max = 100, message = "must be at most 100 characters"),
+        custom = "validation::not_blank"
+    )]
+    pub editor: Option<String>,
+}
+
+/// The rest of an `/{id}/items/{index}` route, after the order id
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct OrderItemPath
+{
+    pub index: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Validate)]
+pub struct OrderItemUpdateRequest
+{
+    pub status: Option<ItemStatus>,
+    #[validate(
+        length(max = 100, message = "must be at most 100 characters"),
+        custom = "validation::not_blank"
+    )]
+    pub assignee: Option<String>,
+}
+
+#[cfg(test)]
+mod tests
+{
+    use actix_web::web;
+    use mongodb::bson::{self, Document};
+
+    use super::*;
+    use crate::products::model::{ModifierOption, Recipe};
+
+    fn option(name: &str, price: Option<&str>) -> ModifierOption
+    {
+        ModifierOption { name: name.to_string(), price: price.map(|price| price.parse().unwrap()) }
+    }
+
+    fn burger() -> Product
+    {
+        Product {
+            id: None,
+            name: "Burger".to_string(),
+            price: "8.00 EUR".parse().unwrap(),
+            kind: ProductKind::Food,
+            station: None,
+            category: None,
+            position: 0,
+            modifiers: vec![
+                ModifierGroup {
+                    name: "Size".to_string(),
+                    required: true,
+                    min: 0,
+                    max: Some(1),
+                    options: vec![option("Regular", None), option("Large", Some("2.50 EUR"))],
+                },
+                ModifierGroup {
+                    name: "Extras".to_string(),
+                    required: false,
+                    min: 0,
+                    max: Some(2),
+                    options: vec![
+                        option("Cheese", Some("1.00 EUR")),
+                        option("Bacon", Some("1.50 EUR")),
+                        option("No onions", None),
+                    ],
+                },
+            ],
+            available: true,
+            remaining: None,
+            recipe: None,
+            allergens: vec![Allergen::Gluten, Allergen::Dairy],
+            dietary: vec![],
+        }
+    }
+
+    fn burger_with_recipe(ingredients: Vec<IngredientAmount>) -> Product
+    {
+        Product {
+            recipe: Some(Recipe { steps: vec![], ingredients, plating: None, prep_minutes: None }),
+            ..burger()
+        }
+    }
+
+    fn choice(group: &str, option: &str) -> ModifierChoice
+    {
+        ModifierChoice { group: group.to_string(), option: option.to_string() }
+    }
+
+    /// A line of one burger, unless a test overrides it
+    struct LineBuilder
+    {
+        product: Product,
+        id: ObjectId,
+        quantity: i32,
+        modifiers: Vec<OrderModifier>,
+        status: ItemStatus,
+    }
+
+    fn line() -> LineBuilder
+    {
+        LineBuilder {
+            product: burger(),
+            id: ObjectId::new(),
+            quantity: 1,
+            modifiers: vec![],
+            status: ItemStatus::Queued,
+        }
+    }
+
+    impl LineBuilder
+    {
+        fn product(self, product: Product) -> Self
+        {
+            LineBuilder { product, ..self }
+        }
+
+        fn id(self, id: ObjectId) -> Self
+        {
+            LineBuilder { id, ..self }
+        }
+
+        fn quantity(self, quantity: i32) -> Self
+        {
+            LineBuilder { quantity, ..self }
+        }
+
+        fn modifiers(self, modifiers: Vec<OrderModifier>) -> Self
+        {
+            LineBuilder { modifiers, ..self }
+        }
+
+        fn status(self, status: ItemStatus) -> Self
+        {
+            LineBuilder { status, ..self }
+        }
+
+        fn build(self) -> OrderItem
+        {
+            let view = ProductView {
+                id: self.id.to_hex(),
+                quantity: self.quantity,
+                modifiers: vec![],
+                note: None,
+            };
+
+            let mut item = OrderItem::new(view, &self.product, self.modifiers, None);
+            item.set_status(self.status, Utc::now());
+            item
+        }
+    }
+
+    /// A received order without lines, unless a test overrides it
+    struct OrderBuilder
+    {
+        number: i32,
+        status: OrderStatus,
+        lines: Vec<OrderItem>,
+        created_at: DateTime<Utc>,
+    }
+
+    fn order() -> OrderBuilder
+    {
+        OrderBuilder {
+            number: 1,
+            status: OrderStatus::Received,
+            lines: vec![],
+            created_at: Utc::now(),
+        }
+    }
+
+    impl OrderBuilder
+    {
+        fn number(self, number: i32) -> Self
+        {
+            OrderBuilder { number, ..self }
+        }
+
+        fn status(self, status: OrderStatus) -> Self
+        {
+            OrderBuilder { status, ..self }
+        }
+
+        fn lines(self, lines: Vec<OrderItem>) -> Self
+        {
+            OrderBuilder { lines, ..self }
+        }
+
+        fn created_at(self, created_at: &str) -> Self
+        {
+            OrderBuilder { created_at: created_at.parse().unwrap(), ..self }
+        }
+
+        fn build(self) -> Order
+        {
+            Order {
+                id: None,
+                order_id: self.number,
+                order_period: String::new(),
+                products: self.lines,
+                total_price: Money::default(),
+                status: self.status,
+                note: None,
+                allergy: false,
+                allergens: vec![],
+                note_history: vec![],
+                created_at: self.created_at,
+                updated_at: self.created_at,
+                previous_status: None,
+            }
+        }
+    }
+
+    #[test]
+    fn prices_lines_with_their_modifiers()
+    {
+        let choices = [choice("Size", "Large"), choice("Extras", "Cheese")];
+
+        let modifiers = choose_modifiers(&burger(), &choices).unwrap();
+
+        let item = line().quantity(2).modifiers(modifiers).build();
+
+        assert_eq!(item.modifiers.len(), 2);
+        assert_eq!(item.line_total.unwrap().to_string(), "23.00 EUR");
+    }
+
+    #[test]
+    fn takes_stock_by_recipe_until_cancelled()
+    {
+        let (bun, beef) = (ObjectId::new(), ObjectId::new());
+        let amount = |ingredient, quantity| IngredientAmount { ingredient, quantity };
+
+        let product =
+            burger_with_recipe(vec![amount(bun, 1.0), amount(beef, 0.15), amount(beef, 0.05)]);
+
+        let mut item = line().product(product).quantity(3).build();
+
+        assert_eq!(item.stock, [amount(bun, 3.0), amount(beef, 0.6)]);
+        assert!(item.returned_stock().is_empty());
+
+        item.set_status(ItemStatus::Cancelled, Utc::now());
+
+        assert_eq!(item.returned_stock(), [amount(bun, 3.0), amount(beef, 0.6)]);
+        assert!(item.returned_stock().is_empty());
+    }
+
+    #[test]
+    fn gives_back_what_unserved_lines_took()
+    {
+        let (bun, burger_id, fries_id) = (ObjectId::new(), ObjectId::new(), ObjectId::new());
+        let product = burger_with_recipe(vec![IngredientAmount { ingredient: bun, quantity: 1.0 }]);
+
+        let line = |id, quantity, status| {
+            line().product(product.clone()).id(id).quantity(quantity).status(status).build()
+        };
+
+        let mut items = vec![
+            line(burger_id, 2, ItemStatus::Queued),
+            line(fries_id, 1, ItemStatus::Served),
+            line(burger_id, 1, ItemStatus::Ready),
+            line(fries_id, 4, ItemStatus::Cancelled),
+        ];
+        // the cancelled line gave its stock back when it was cancelled
+        items[3].stock.clear();
+
+        let returned = Returned::cancel_unserved(&mut items, Utc::now());
+
+        assert_eq!(returned.products, [(burger_id, 3)]);
+        assert_eq!(returned.stock, [IngredientAmount { ingredient: bun, quantity: 3.0 }]);
+        assert_eq!(items[1].status, ItemStatus::Served);
+        assert!(items.iter().all(|item| item.status != ItemStatus::Queued));
+
+        assert!(Returned::cancel_unserved(&mut items, Utc::now()).is_empty());
+    }
+
+    #[test]
+    fn collects_allergens_of_every_line()
+    {
+        let fish = Product { allergens: vec![Allergen::Fish, Allergen::Gluten], ..burger() };
+
+        let order = order().lines(vec![line().build(), line().product(fish).build()]).build();
+
+        assert_eq!(order.products[1].allergens, [Allergen::Fish, Allergen::Gluten]);
+        assert_eq!(order.line_allergens(), [Allergen::Gluten, Allergen::Dairy, Allergen::Fish]);
+    }
+
+    #[test]
+    fn allows_only_the_listed_transitions()
+    {
+        use OrderStatus::*;
+
+        let statuses = [Received, Accepted, InPreparation, Ready, Served, Completed, Cancelled];
+
+        let allowed: Vec<(OrderStatus, OrderStatus)> = statuses
+            .iter()
+            .flat_map(|from| statuses.iter().map(move |to| (*from, *to)))
+            .filter(|(from, to)| from.can_transition_to(*to))
+            .collect();
+
+        assert_eq!(
+            allowed,
+            [
+                (Received, Accepted),
+                (Received, Cancelled),
+                (Accepted, InPreparation),
+                (Accepted, Cancelled),
+                (InPreparation, Ready),
+                (InPreparation, Cancelled),
+                (Ready, Served),
+                (Ready, Cancelled),
+                (Served, Completed),
+            ]
+        );
+
+        assert!(Received.can_reach(Completed));
+        assert!(!Served.can_reach(Cancelled));
+        assert!(!Completed.can_reach(Received));
+    }
+
+    #[test]
+    fn moves_items_forward_and_back_to_the_kitchen()
+    {
+        use ItemStatus::*;
+
+        assert!(Queued.can_transition_to(Ready));
+        assert!(Ready.can_transition_to(InPreparation));
+        assert!(!InPreparation.can_transition_to(Queued));
+        assert!(!Queued.can_transition_to(Served));
+        assert!(Served.next_statuses().is_empty() && Cancelled.next_statuses().is_empty());
+
+        let mut bumps = vec![Queued];
+
+        while let Some(next) = bumps.last().unwrap().bumped()
+        {
+            assert!(bumps.last().unwrap().can_transition_to(next));
+            bumps.push(next);
+        }
+
+        assert_eq!(bumps, [Queued, InPreparation, Ready, Served]);
+    }
+
+    #[test]
+    fn derives_statuses_only_along_allowed_jumps()
+    {
+        use ItemStatus::{Queued, Ready, Served};
+
+        let items = |statuses: &[ItemStatus]| -> Vec<OrderItem> {
+            statuses.iter().map(|status| line().status(*status).build()).collect()
+        };
+
+        // skipping the steps the kitchen went through is fine
+        assert_eq!(OrderStatus::Received.derive_from(&items(&[Ready])), OrderStatus::Ready);
+        assert_eq!(
+            OrderStatus::Received.derive_from(&items(&[Ready, Queued])),
+            OrderStatus::InPreparation
+        );
+        assert_eq!(OrderStatus::Accepted.derive_from(&items(&[Served])), OrderStatus::Served);
+
+        // the one way back, for items sent back to the kitchen
+        let mut recalled = items(&[Ready, Ready]);
+        recalled[0].set_status(ItemStatus::InPreparation, Utc::now());
+        assert_eq!(OrderStatus::Ready.derive_from(&recalled), OrderStatus::InPreparation);
+
+        // an order served by hand does not fall back to preparation
+        assert_eq!(OrderStatus::Served.derive_from(&recalled), OrderStatus::Served);
+        assert_eq!(OrderStatus::Completed.derive_from(&items(&[Queued])), OrderStatus::Completed);
+    }
+
+    #[test]
+    fn moves_items_along_with_an_order_set_by_hand()
+    {
+        let mut served = line().build();
+        served.follow(OrderStatus::Served, Utc::now());
+        assert_eq!(served.status, ItemStatus::Served);
+        assert!(served.ready_at.is_some() && served.served_at.is_some());
+
+        let mut cancelled = line().status(ItemStatus::Cancelled).build();
+        cancelled.follow(OrderStatus::Ready, Utc::now());
+        assert_eq!(cancelled.status, ItemStatus::Cancelled);
+
+        let mut accepted = line().build();
+        accepted.follow(OrderStatus::Accepted, Utc::now());
+        assert_eq!(accepted.status, ItemStatus::Queued);
+    }
+
+    #[test]
+    fn selects_orders_by_status_day_and_product()
+    {
+        let id = ObjectId::new();
+
+        let placed = |number, status, created_at| {
+            order()
+                .number(number)
+                .status(status)
+                .lines(vec![line().id(id).build()])
+                .created_at(created_at)
+                .build()
+        };
+
+        let orders = [
+            placed(1, OrderStatus::Cancelled, "2026-10-09T23:59:59.999Z"),
+            placed(2, OrderStatus::Cancelled, "2026-10-10T00:00:00Z"),
+            placed(3, OrderStatus::Served, "2026-10-10T12:30:00.250Z"),
+            placed(4, OrderStatus::Cancelled, "2026-10-10T21:15:00.5Z"),
+            placed(5, OrderStatus::Cancelled, "2026-10-11T00:00:00Z"),
+        ];
+
+        let query = |text: &str| web::Query::<OrderListQuery>::from_query(text).unwrap().0;
+
+        let numbers = |query: OrderListQuery| -> Vec<i32> {
+            let filter = query.filter();
+
+            orders
+                .iter()
+                .filter(|order| filter.matches(&storage::to_document(order).unwrap()))
+                .map(|order| order.order_id)
+                .collect()
+        };
+
+        assert_eq!(numbers(query("status=Cancelled&from=2026-10-10&to=2026-10-10")), [2, 4]);
+        assert_eq!(numbers(query("status=Served,Cancelled&to=2026-10-10T12:30:00.25Z")), [1, 2]);
+        assert_eq!(numbers(query("from=2026-10-10T21:15:00.5Z&number=4")), [4]);
+        assert_eq!(numbers(query(&format!("product={}", id))).len(), 5);
+        assert!(numbers(query(&format!("product={}", ObjectId::new()))).is_empty());
+        assert_eq!(query("").sort(), Sort::new("created_at", SortOrder::Desc));
+    }
+
+    #[test]
+    fn keeps_times_within_a_second_in_order()
+    {
+        let placed = |created_at| storage::to_document(&order().created_at(created_at).build());
+
+        // chrono writes as few fraction digits as it can, so these differ in width
+        let mut documents: Vec<Document> = [
+            "2026-10-10T18:00:00.5Z",
+            "2026-10-10T18:00:00Z",
+            "2026-10-10T18:00:00.500001Z",
+            "2026-10-10T18:00:00.25Z",
+        ]
+        .map(|created_at| placed(created_at).unwrap())
+        .to_vec();
+
+        let query = |text: &str| web::Query::<OrderListQuery>::from_query(text).unwrap().0;
+
+        let time = |document: &Document| {
+            let order: Order = bson::from_document(document.clone()).unwrap();
+
+            order.created_at.format("%S%.f").to_string()
+        };
+
+        query("").sort().apply(&mut documents);
+
+        let sorted: Vec<String> = documents.iter().map(time).collect();
+
+        assert_eq!(sorted, ["00.500001", "00.500", "00.250", "00"]);
+
+        let selected = |text: &str| -> Vec<String> {
+            let filter = query(text).filter();
+
+            documents.iter().filter(|document| filter.matches(document)).map(time).collect()
+        };
+
+        assert_eq!(selected("from=2026-10-10T18:00:00.5Z"), ["00.500001", "00.500"]);
+        assert_eq!(selected("to=2026-10-10T18:00:00.5Z"), ["00.250", "00"]);
+        assert_eq!(selected("from=2026-10-10T18:00:00Z&to=2026-10-10T18:00:00.25Z"), ["00"]);
+    }
+
+    #[test]
+    fn rejects_modifiers_that_do_not_fit()
+    {
+        let product = burger();
+
+        let invalid = [
+            vec![],
+            vec![choice("Size", "Huge")],
+            vec![choice("Size", "Large"), choice("Size", "Regular")],
+            vec![choice("Size", "Large"), choice("Extras", "Cheese"), choice("Extras", "Cheese")],
+            vec![
+                choice("Size", "Large"),
+                choice("Extras", "Cheese"),
+                choice("Extras", "Bacon"),
+                choice("Extras", "No onions"),
+            ],
+        ];
+
+        for choices in invalid
+        {
+            assert!(choose_modifiers(&product, &choices).is_err(), "{:?}", choices);
+        }
+    }
 }
\ No newline at end of file
@@ -0,0 +1,109 @@
+use chrono::{DateTime, Datelike, Duration, Utc};
+use std::env;
+
+use crate::constants;
+
+/// When ticket numbers start again from one
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ResetSchedule
+{
+    Never,
+    Daily,
+    Weekly,
+}
+
+/// Splits time into numbering periods, each with its own ticket counter
+#[derive(Debug, Clone)]
+pub struct OrderNumbering
+{
+    schedule: ResetSchedule,
+    reset_hour: u32,
+}
+
+impl OrderNumbering
+{
+    /// Creates a numbering whose periods start at `reset_hour` (UTC)
+    pub fn new(schedule: ResetSchedule, reset_hour: u32) -> Self
+    {
+        OrderNumbering {
+            schedule,
+            reset_hour: reset_hour % 24,
+        }
+    }
+
+    /// Reads the schedule from `ORDER_NUMBER_RESET` (`daily`, `weekly` or `never`)
+    /// and the hour the service day starts from `ORDER_NUMBER_RESET_HOUR`
+    pub fn from_env() -> Self
+    {
+        let schedule = match env::var("ORDER_NUMBER_RESET")
+            .unwrap_or_else(|_| constants::DEFAULT_ORDER_NUMBER_RESET.to_string())
+            .to_lowercase()
+            .as_str()
+        {
+            "never" => ResetSchedule::Never,
+            "daily" => ResetSchedule::Daily,
+            "weekly" => ResetSchedule::Weekly,
+            other =>
+            {
+                warn!("Unknown order number reset schedule '{}', using daily.", other);
+                ResetSchedule::Daily
+            },
+        };
+
+        let reset_hour = env::var("ORDER_NUMBER_RESET_HOUR")
+            .ok()
+            .and_then(|hour| hour.parse().ok())
+            .unwrap_or(constants::DEFAULT_ORDER_NUMBER_RESET_HOUR);
+
+        OrderNumbering::new(schedule, reset_hour)
+    }
+
+    /// Returns the key of the period `time` falls into, e.g. `2022-10-19` for a daily schedule
+    pub fn period(&self, time: DateTime<Utc>) -> String
+    {
+        let service_time = time - Duration::hours(self.reset_hour as i64);
+
+        match self.schedule
+        {
+            ResetSchedule::Never => "all".to_string(),
+            ResetSchedule::Daily => service_time.format("%Y-%m-%d").to_string(),
+            ResetSchedule::Weekly =>
+            {
+                let week = service_time.iso_week();
+                format!("{}-W{:02}", week.year(), week.week())
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn at(time: &str) -> DateTime<Utc>
+    {
+        time.parse().unwrap()
+    }
+
+    #[test]
+    fn starts_periods_at_the_reset_hour()
+    {
+        let daily = OrderNumbering::new(ResetSchedule::Daily, 4);
+
+        assert_eq!(daily.period(at("2026-10-10T03:59:59Z")), "2026-10-09");
+        assert_eq!(daily.period(at("2026-10-10T04:00:00Z")), "2026-10-10");
+
+        // Sunday night belongs to the week that started on Monday
+        let weekly = OrderNumbering::new(ResetSchedule::Weekly, 4);
+
+        assert_eq!(weekly.period(at("2026-10-12T03:00:00Z")), "2026-W41");
+        assert_eq!(weekly.period(at("2026-10-12T05:00:00Z")), "2026-W42");
+        assert_eq!(weekly.period(at("2027-01-01T12:00:00Z")), "2026-W53");
+
+        let never = OrderNumbering::new(ResetSchedule::Never, 28);
+
+        assert_eq!(never.reset_hour, 4);
+        assert_eq!(never.period(at("2026-10-10T12:00:00Z")), "all");
+    }
+}
\ No newline at end of file
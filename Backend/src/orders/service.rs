@@ -5,62 +5,53 @@ use crate::broadcast;
 use crate::common_model::CommonResponse;
 use crate::console;
 use crate::database::Database;
+use crate::error::ApiError;
+use crate::extract::{EntityId, ValidJson};
+use crate::events::{DomainEvent, ItemRecipe};
+use crate::products::collection::ProductCollectionError;
 use actix_web::Responder;
 use actix_web::{web, HttpResponse};
+use mongodb::bson::oid::ObjectId;
 use env_logger::{Builder, Env};
 use log;
 use serde::{Deserialize, Serialize};
 
 pub async fn create(
     database_data: web::Data<Database>,
-    content: web::Json<OrderCreateRequest>,
-) -> impl Responder {
+    broadcaster: web::Data<std::sync::Mutex<broadcast::Broadcaster>>,
+    content: ValidJson<OrderCreateRequest>,
+) -> Result<HttpResponse, ApiError> {
     info!("Create Order requested...");
 
     let collection = database_data.orders().await;
     let collection_products = database_data.products().await;
+    let collection_ingredients = database_data.ingredients().await;
 
-    let insertion_result = collection
-        .create(content.into_inner(), collection_products)
-        .await;
-
-    match insertion_result {
-        Ok(result) => {
-            let inserted_data = result.inserted_id;
-            HttpResponse::Ok().json(inserted_data)
-        },
-        Err(error) => match error {
-            OrderCollectionError::OneOfProductsNotFound => {
-                let response = CommonResponse::<Order> {
-                    message: "One of products not found.".to_string(),
-                    data: None,
-                };
-
-                HttpResponse::BadRequest().json(response)
-            },
-            OrderCollectionError::CustomError(message) => {
-                let response = CommonResponse::<Order> {
-                    message,
-                    data: None,
-                };
+    let (order, products, low) = collection
+        .create(content.into_inner(), collection_products, collection_ingredients)
+        .await?;
 
-                HttpResponse::BadRequest().json(response)
-            },
-            _ => {
-                let response = CommonResponse::<Order> {
-                    message: "Unknown error.".to_string(),
-                    data: None,
-                };
-                HttpResponse::BadRequest().json(response)
-            },
-        },
+    let inserted_data = order.id;
+
+    DomainEvent::OrderCreated { order: order.clone() }.publish(&broadcaster);
+
+    publish_recipes(&database_data, &broadcaster, order).await;
+
+    for ingredient in low {
+        DomainEvent::StockLow { ingredient }.publish(&broadcaster);
+    }
+
+    for product in products {
+        DomainEvent::ProductAvailabilityChanged { product }.publish(&broadcaster);
     }
+
+    Ok(HttpResponse::Ok().json(inserted_data))
 }
 
 pub async fn list(
     database_data: web::Data<Database>,
     query: web::Query<OrderListQuery>,
-) -> impl Responder {
+) -> Result<HttpResponse, ApiError> {
     info!("[Service] List Order requested...");
 
     let offset = query.offset.unwrap_or(0);
@@ -68,124 +59,257 @@ pub async fn list(
 
     let collection = database_data.orders().await;
 
-    let result = collection.list(offset, limit).await;
+    let orders = collection.list(&query.filter(), Some(&query.sort()), offset, limit).await?;
 
-    match result {
-        Ok(orders) => HttpResponse::Ok().json(orders),
-        Err(error) => {
-            error!("[Service] Failed to get order. Error: {:?}", error);
-            HttpResponse::InternalServerError().finish()
-        },
-    }
+    Ok(HttpResponse::Ok().json(orders))
 }
 
 pub async fn update(
     database_data: web::Data<Database>,
     broadcaster: web::Data<std::sync::Mutex<broadcast::Broadcaster>>,
-    id: web::Path<String>,
-    content: web::Json<OrderUpdateRequest>,
-) -> impl Responder {
-    info!("Update Product requested...");
+    id: EntityId,
+    content: ValidJson<OrderUpdateRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let internal_id = id.into_inner();
+    let internal_content = content.into_inner();
+    let status = internal_content.status;
+
+    info!("Update Order {} requested, status {:?}...", internal_id, status);
+
+    let collection = database_data.orders().await;
+
+    let (order, returned) = collection
+        .update(internal_id, internal_content)
+        .await?;
+
+    give_back(&database_data, &broadcaster, &returned).await;
+
+    let response = CommonResponse::<Order> {
+        message: format!("{} order status updated as {:?}.", internal_id, status),
+        data: None,
+    };
+
+    DomainEvent::OrderUpdated { order }.publish(&broadcaster);
+
+    Ok(HttpResponse::Ok().json(response))
+}
+
+pub async fn update_item(
+    database_data: web::Data<Database>,
+    broadcaster: web::Data<std::sync::Mutex<broadcast::Broadcaster>>,
+    id: EntityId,
+    path: web::Path<OrderItemPath>,
+    content: ValidJson<OrderItemUpdateRequest>,
+) -> Result<HttpResponse, ApiError> {
+    info!("Update Order Item requested...");
 
     let internal_id = id.into_inner();
+    let index = path.index;
+
     let internal_content = content.into_inner();
+    let status_requested = internal_content.status.is_some();
 
     let collection = database_data.orders().await;
 
-    let update_result = collection
-        .update(internal_id.clone(), internal_content.clone())
-        .await;
-
-    match update_result {
-        Ok(result) => {
-            let updated_data = result.modified_count;
-            let response = CommonResponse::<Order> {
-                message: format!(
-                    "{} order status updated as {:?}.",
-                    internal_id.clone(),
-                    internal_content.clone().clone()
-                ),
-                data: None,
-            };
-
-            let broadcast_message = serde_json::to_string(&response.clone()).unwrap();
-
-            broadcast::broadcast("order_update".to_string(), broadcast_message, broadcaster);
-
-            HttpResponse::Ok().json(response)
-        },
-        Err(error) => match error {
-            OrderCollectionError::OrderNotModified => {
-                let response = CommonResponse::<Order> {
-                    message: "Order Not Modified".to_string(),
-                    data: None,
-                };
-                HttpResponse::NotFound().json(response)
-            },
-            OrderCollectionError::CustomError(message) => {
-                let response = CommonResponse::<Order> {
-                    message,
-                    data: None,
-                };
-                HttpResponse::BadRequest().json(response)
+    let (order, returned) = collection
+        .update_item(internal_id, index, internal_content)
+        .await?;
+
+    give_back(&database_data, &broadcaster, &returned).await;
+
+    let event = if status_requested {
+        DomainEvent::item_status_changed(order.clone(), index)
+    } else {
+        DomainEvent::OrderUpdated { order: order.clone() }
+    };
+
+    event.publish(&broadcaster);
+
+    let response = CommonResponse::<Order> {
+        message: format!(
+            "{} order item {} status is {:?}, order status is {:?}.",
+            internal_id, index, order.products[index].status, order.status
+        ),
+        data: Some(order),
+    };
+
+    Ok(HttpResponse::Ok().json(response))
+}
+
+pub async fn set_note(
+    database_data: web::Data<Database>,
+    broadcaster: web::Data<std::sync::Mutex<broadcast::Broadcaster>>,
+    id: EntityId,
+    content: ValidJson<NoteUpdateRequest>,
+) -> Result<HttpResponse, ApiError> {
+    info!("Update Order Note requested...");
+
+    let collection = database_data.orders().await;
+
+    let order = collection
+        .set_note(id.into_inner(), None, content.into_inner())
+        .await?;
+
+    publish_note(&broadcaster, &order, None);
+
+    let response = CommonResponse::<Order> {
+        message: "1 order notes updated.".to_string(),
+        data: Some(order),
+    };
+
+    Ok(HttpResponse::Ok().json(response))
+}
+
+pub async fn set_item_note(
+    database_data: web::Data<Database>,
+    broadcaster: web::Data<std::sync::Mutex<broadcast::Broadcaster>>,
+    id: EntityId,
+    path: web::Path<OrderItemPath>,
+    content: ValidJson<NoteUpdateRequest>,
+) -> Result<HttpResponse, ApiError> {
+    info!("Update Order Item Note requested...");
+
+    let index = path.index;
+
+    let collection = database_data.orders().await;
+
+    let order = collection
+        .set_note(id.into_inner(), Some(index), content.into_inner())
+        .await?;
+
+    publish_note(&broadcaster, &order, Some(index));
+
+    let response = CommonResponse::<Order> {
+        message: "1 order notes updated.".to_string(),
+        data: Some(order),
+    };
+
+    Ok(HttpResponse::Ok().json(response))
+}
+
+/// Publishes the recipes of the lines of a new order, one `item_recipes` event for
+/// each station that prepares them
+async fn publish_recipes(
+    database_data: &Database,
+    broadcaster: &web::Data<std::sync::Mutex<broadcast::Broadcaster>>,
+    order: Order,
+) {
+    let collection_products = database_data.products().await;
+    let collection_ingredients = database_data.ingredients().await;
+
+    let mut stations: Vec<(Option<String>, Vec<ItemRecipe>)> = Vec::new();
+
+    for (index, item) in order.products.iter().enumerate() {
+        let id = match ObjectId::parse_str(&item.id) {
+            Ok(id) => id,
+            Err(_) => continue,
+        };
+
+        let recipe = match collection_products.recipe(id, collection_ingredients).await {
+            Ok(recipe) => recipe,
+            Err(ProductCollectionError::RecipeNotFound) => continue,
+            Err(error) => {
+                error!("Failed to get recipe of order item. Error: {:?}", error);
+                continue;
             },
-            _ => {
-                let response = CommonResponse::<Order> {
-                    message: "Unknown error.".to_string(),
-                    data: None,
-                };
-                HttpResponse::BadRequest().json(response)
+        };
+
+        let item_recipe = ItemRecipe { index, quantity: item.quantity, recipe };
+
+        match stations.iter_mut().find(|(station, _)| *station == item.station) {
+            Some((_, items)) => items.push(item_recipe),
+            None => stations.push((item.station.clone(), vec![item_recipe])),
+        }
+    }
+
+    for (station, items) in stations {
+        DomainEvent::ItemRecipes { order: order.clone(), station, items }.publish(broadcaster);
+    }
+}
+
+/// Puts the stock taken by cancelled or deleted lines back and gives their products
+/// the quantities back; the change is already saved, so failures are only logged
+async fn give_back(
+    database_data: &Database,
+    broadcaster: &web::Data<std::sync::Mutex<broadcast::Broadcaster>>,
+    returned: &Returned,
+) {
+    if !returned.stock.is_empty() {
+        if let Err(error) = database_data.ingredients().await.put_back(&returned.stock).await {
+            error!("Failed to put ingredients back into stock. Error: {:?}", error);
+        }
+    }
+
+    let collection_products = database_data.products().await;
+
+    for (id, quantity) in &returned.products {
+        match collection_products.release(*id, *quantity).await {
+            Ok(Some(product)) => {
+                DomainEvent::ProductAvailabilityChanged { product }.publish(broadcaster);
             },
-        },
+            // products without a count, or deleted since, have nothing to take back
+            Ok(None) | Err(ProductCollectionError::ProductNotFound) => (),
+            Err(error) => error!("Failed to give back ordered products. Error: {:?}", error),
+        }
     }
 }
 
-pub async fn delete(database_data: web::Data<Database>, id: web::Path<String>) -> impl Responder {
+fn publish_note(
+    broadcaster: &web::Data<std::sync::Mutex<broadcast::Broadcaster>>,
+    order: &Order,
+    index: Option<usize>,
+) {
+    let note = match index {
+        Some(index) => order.products[index].note.clone(),
+        None => order.note.clone(),
+    };
+
+    DomainEvent::NoteChanged { order: order.clone(), index, note }.publish(broadcaster);
+}
+
+pub async fn delete(
+    database_data: web::Data<Database>,
+    broadcaster: web::Data<std::sync::Mutex<broadcast::Broadcaster>>,
+    id: EntityId,
+) -> Result<HttpResponse, ApiError> {
     let collection = database_data.orders().await;
 
-    let result = collection.delete(id.into_inner()).await;
-
-    match result {
-        Ok(result) => {
-            if result.deleted_count == 0 {
-                let response = CommonResponse::<Order> {
-                    message: "Order not found.".to_string(),
-                    data: None,
-                };
-                HttpResponse::NotFound().json(response)
-            } else {
-                let response = CommonResponse::<Order> {
-                    message: format!("{} orders deleted.", result.deleted_count),
-                    data: None,
-                };
-                HttpResponse::Ok().json(response)
-            }
-        },
-        Err(error) => {
-            let response = CommonResponse::<String> {
-                message: "Unknown error.".to_string(),
-                data: None,
-            };
-
-            error!("Failed to delete order. Error: {:?}", error);
-
-            HttpResponse::InternalServerError().json(response)
-        },
-    }
+    let (order, returned) = collection.delete(id.into_inner()).await?;
+
+    give_back(&database_data, &broadcaster, &returned).await;
+
+    DomainEvent::OrderDeleted { order }.publish(&broadcaster);
+
+    let response = CommonResponse::<Order> {
+        message: "1 orders deleted.".to_string(),
+        data: None,
+    };
+
+    Ok(HttpResponse::Ok().json(response))
 }
 
-pub async fn get(database_data: web::Data<Database>, id: web::Path<String>) -> impl Responder {
-    info!("Get Product by ID requested...");
+pub async fn get(
+    database_data: web::Data<Database>,
+    id: EntityId,
+) -> Result<HttpResponse, ApiError> {
+    info!("Get Order by ID requested...");
 
     let collection = database_data.orders().await;
 
-    let result = collection.get(id.into_inner()).await;
+    let order = collection.get(id.into_inner()).await?;
 
-    match result {
-        Ok(product) => HttpResponse::Ok().json(product),
-        Err(error) => {
-            error!("Failed to get product. Error: {:?}", error);
-            HttpResponse::InternalServerError().finish()
-        },
-    }
+    Ok(HttpResponse::Ok().json(order))
+}
+
+pub async fn get_by_number(
+    database_data: web::Data<Database>,
+    number: web::Path<i32>,
+) -> Result<HttpResponse, ApiError> {
+    info!("Get Order by number requested...");
+
+    let collection = database_data.orders().await;
+
+    let order = collection.get_by_number(number.into_inner()).await?;
+
+    Ok(HttpResponse::Ok().json(order))
 }
@@ -0,0 +1,245 @@
+use std::sync::Mutex;
+
+use actix_web::{web, Error, HttpRequest, HttpResponse};
+use actix_ws::{Message, ProtocolError, Session};
+use futures::{Stream, StreamExt};
+use tokio_stream::wrappers::ReceiverStream;
+
+use super::model::{DisplayCommand, OrderEventsQuery, OrderStatus, OrderUpdateRequest};
+use crate::broadcast::{Broadcaster, Event};
+use crate::database::Database;
+use crate::error::ApiError;
+use crate::extract::parse_id;
+use crate::events::DomainEvent;
+
+// what the socket task waits on: a broadcast event or a frame from the display
+enum Incoming {
+    Event(Event),
+    Frame(Result<Message, ProtocolError>),
+}
+
+/// Opens a WebSocket carrying the order event stream, which also accepts display commands
+///
+/// Events are sent as `{ "id", "event", "data" }` text frames and take the same
+/// filters as the event stream. Commands are JSON text frames such as
+/// `{ "command": "bump_item", "order": "...", "index": 0 }`, answered with a
+/// `command_result` or `command_error` frame.
+pub async fn order_socket(
+    request: HttpRequest,
+    body: web::Payload,
+    database_data: web::Data<Database>,
+    broadcaster: web::Data<Mutex<Broadcaster>>,
+    query: web::Query<OrderEventsQuery>,
+) -> Result<HttpResponse, Error> {
+    let (response, session, frames) = actix_ws::handle(&request, body)?;
+
+    let query = query.into_inner();
+    let last_event_id = query.last_event_id;
+
+    let events = broadcaster.lock().unwrap().subscribe(
+        vec![Event::new("internal_status", "connected")],
+        query.filter(),
+        last_event_id,
+    );
+
+    let incoming = futures::stream::select(
+        ReceiverStream::new(events).map(Incoming::Event),
+        frames.map(Incoming::Frame),
+    );
+
+    actix_rt::spawn(run(session, incoming, database_data, broadcaster));
+
+    Ok(response)
+}
+
+async fn run(
+    mut session: Session,
+    mut incoming: impl Stream<Item = Incoming> + Unpin,
+    database_data: web::Data<Database>,
+    broadcaster: web::Data<Mutex<Broadcaster>>,
+) {
+    while let Some(incoming) = incoming.next().await {
+        let sent = match incoming {
+            Incoming::Event(event) => session.text(event.to_json()).await,
+            Incoming::Frame(Ok(Message::Text(text))) => {
+                let reply = execute(&text, &database_data, &broadcaster).await;
+                session.text(reply.to_json()).await
+            },
+            Incoming::Frame(Ok(Message::Ping(bytes))) => session.pong(&bytes).await,
+            Incoming::Frame(Ok(Message::Close(reason))) => {
+                session.close(reason).await.unwrap_or(());
+                return;
+            },
+            Incoming::Frame(Ok(_)) => Ok(()),
+            Incoming::Frame(Err(error)) => {
+                warn!("Order socket closed after a protocol error: {:?}", error);
+                break;
+            },
+        };
+
+        // the display went away, dropping the subscription ends its broadcasts
+        if sent.is_err() {
+            return;
+        }
+    }
+
+    session.close(None).await.unwrap_or(());
+}
+
+// runs a display command, publishing the change like the REST handlers do
+async fn execute(
+    text: &str,
+    database_data: &web::Data<Database>,
+    broadcaster: &web::Data<Mutex<Broadcaster>>,
+) -> Event {
+    let command: DisplayCommand = match serde_json::from_str(text) {
+        Ok(command) => command,
+        Err(error) => {
+            return command_error(ApiError::BadRequest(format!("Invalid command: {}.", error)))
+        },
+    };
+
+    info!("Display command {:?} received...", command);
+
+    let id = match parse_id(command.order()) {
+        Ok(id) => id,
+        Err(error) => return command_error(error),
+    };
+
+    let collection = database_data.orders().await;
+
+    let result = match command {
+        DisplayCommand::BumpItem { index, .. } => collection
+            .bump_item(id, index)
+            .await
+            .map(|order| DomainEvent::item_status_changed(order, index)),
+        DisplayCommand::RecallOrder { .. } => collection
+            .recall(id)
+            .await
+            .map(|order| DomainEvent::OrderUpdated { order }),
+        DisplayCommand::Acknowledge { .. } => {
+            let content = OrderUpdateRequest { status: OrderStatus::Accepted };
+            collection
+                .update(id, content)
+                .await
+                .map(|(order, _)| DomainEvent::OrderUpdated { order })
+        },
+    };
+
+    let event = match result {
+        Ok(event) => event,
+        Err(error) => return command_error(ApiError::from(error)),
+    };
+
+    event.publish(broadcaster);
+
+    let message = serde_json::to_string(&event).unwrap();
+
+    Event::new("command_result", &message)
+}
+
+// answers a failed command with the same body a REST request would get
+fn command_error(error: ApiError) -> Event {
+    if let ApiError::Internal(details) = &error {
+        error!("Display command failed: {}", details);
+    }
+
+    Event::new("command_error", &serde_json::to_string(&error.to_response()).unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::database::tests::{memory, place, product};
+
+    // places an order of one soup, returning its id
+    async fn place_soup(database: &Database) -> String {
+        let soup = json!({ "name": "Soup", "price": "4.50", "kind": "Food" });
+        let soup = product(database, soup).await.id.unwrap();
+
+        let order = place(database, &[soup], 1).await.unwrap();
+
+        order.id.unwrap().to_hex()
+    }
+
+    // runs a command, returning the event name and data of its reply
+    async fn reply(
+        command: &str,
+        database: &web::Data<Database>,
+        broadcaster: &web::Data<Mutex<Broadcaster>>,
+    ) -> (String, Value) {
+        let event = execute(command, database, broadcaster).await;
+
+        (event.name, serde_json::from_str(&event.data).unwrap())
+    }
+
+    #[actix_web::test]
+    async fn runs_display_commands_and_publishes_their_events() {
+        let database = web::Data::new(memory().await);
+        let broadcaster = web::Data::new(Mutex::new(Broadcaster::new()));
+        let mut display = broadcaster.lock().unwrap().subscribe(vec![], Default::default(), None);
+
+        let order = place_soup(&database).await;
+        let command = |command: &str, index: Option<usize>| {
+            let mut command = json!({ "command": command, "order": order });
+            if let Some(index) = index {
+                command["index"] = json!(index);
+            }
+            command.to_string()
+        };
+
+        let (name, data) = reply(&command("acknowledge", None), &database, &broadcaster).await;
+
+        assert_eq!(name, "command_result");
+        assert_eq!(data["type"], "order_updated");
+        assert_eq!(data["order"]["status"], "Accepted");
+
+        let bump = command("bump_item", Some(0));
+
+        for expected in ["InPreparation", "Ready"] {
+            let (_, data) = reply(&bump, &database, &broadcaster).await;
+
+            assert_eq!(data["type"], "item_status_changed");
+            assert_eq!(data["item"]["status"], expected);
+        }
+
+        let (_, data) = reply(&command("recall_order", None), &database, &broadcaster).await;
+
+        assert_eq!(data["order"]["status"], "InPreparation");
+        assert_eq!(data["order"]["products"][0]["status"], "InPreparation");
+
+        let published: Vec<String> =
+            std::iter::from_fn(|| display.try_recv().ok()).map(|event| event.name).collect();
+
+        assert_eq!(
+            published,
+            ["order_updated", "item_status_changed", "item_status_changed", "order_updated"]
+        );
+    }
+
+    #[actix_web::test]
+    async fn answers_failed_commands_with_the_error_body() {
+        let database = web::Data::new(memory().await);
+        let broadcaster = web::Data::new(Mutex::new(Broadcaster::new()));
+
+        let order = place_soup(&database).await;
+        let missing = mongodb::bson::oid::ObjectId::new().to_hex();
+
+        let failures = [
+            (json!({ "command": "shout", "order": order }), "bad_request"),
+            (json!({ "command": "acknowledge", "order": "not-an-id" }), "invalid_id"),
+            (json!({ "command": "acknowledge", "order": missing }), "order_not_found"),
+            (json!({ "command": "bump_item", "order": order, "index": 3 }), "order_item_not_found"),
+            (json!({ "command": "recall_order", "order": order }), "invalid_status_transition"),
+        ];
+
+        for (command, code) in failures {
+            let (name, data) = reply(&command.to_string(), &database, &broadcaster).await;
+
+            assert_eq!(name, "command_error");
+            assert_eq!(data["code"], code, "{}", command);
+        }
+    }
+}
\ No newline at end of file
@@ -1,21 +1,147 @@
 use std::{sync::Mutex, collections::HashMap};
 
-use actix_web::{web, Responder, HttpResponse, http::header};
+use actix_web::{web, Responder, HttpRequest, HttpResponse, http::header};
 
-use crate::{broadcast};
+use super::model::{Order, OrderEventsQuery};
+use crate::broadcast::{self, EventTags};
+
+/// Tags order events so clients can subscribe to a station, status or order
+///
+/// An order that just changed status carries its previous status as well.
+pub fn order_tags(order: &Order) -> EventTags {
+    let mut tags = EventTags::new();
+
+    if let Some(id) = order.id {
+        tags.insert("order".to_string(), vec![id.to_hex()]);
+    }
+
+    // an order leaving a status is news to those watching that status too
+    let statuses = std::iter::once(order.status).chain(order.previous_status);
+    tags.insert("status".to_string(), statuses.map(|status| format!("{:?}", status)).collect());
+
+    let mut stations: Vec<String> = order
+        .products
+        .iter()
+        .filter_map(|item| item.station.clone())
+        .collect();
+    stations.sort();
+    stations.dedup();
+
+    tags.insert("station".to_string(), stations);
+
+    tags.insert("allergy".to_string(), vec![order.allergy.to_string()]);
+
+    tags
+}
 
 pub async fn order_update(
+    request: HttpRequest,
     broadcaster: web::Data<Mutex<broadcast::Broadcaster>>,
+    query: web::Query<OrderEventsQuery>,
 ) -> impl Responder {
 
     let mut new_hashmap = HashMap::<String, String>::new();
 
     new_hashmap.insert("order_update".to_string(), "".to_string());
 
-    let rx = broadcaster.lock().unwrap().new_client(new_hashmap);
+    // sent by browsers when an EventSource reconnects
+    let last_event_id = request
+        .headers()
+        .get("Last-Event-ID")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse::<u64>().ok())
+        .or(query.last_event_id);
+
+    let rx = broadcaster
+        .lock()
+        .unwrap()
+        .new_client(new_hashmap, query.into_inner().filter(), last_event_id);
 
     HttpResponse::Ok()
         .append_header(header::ContentType("text/event-stream".parse().unwrap()))
         .no_chunking(1024 * 5)
         .streaming(rx)
+}
+
+#[cfg(test)]
+mod tests {
+    use mongodb::bson::oid::ObjectId;
+    use serde_json::json;
+
+    use super::*;
+    use crate::orders::model::OrderStatus;
+
+    // an order with a line prepared at each of `stations`
+    fn order(status: &str, stations: &[&str]) -> Order {
+        let lines: Vec<_> = stations
+            .iter()
+            .map(|station| {
+                json!({ "id": ObjectId::new().to_hex(), "quantity": 1, "station": station })
+            })
+            .collect();
+
+        serde_json::from_value(json!({
+            "_id": ObjectId::new(),
+            "order_id": 1,
+            "products": lines,
+            "total_price": "0.00 EUR",
+            "status": status,
+            "created_at": "2024-05-01T12:00:00Z",
+            "updated_at": "2024-05-01T12:00:00Z",
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn follows_the_orders_a_display_asks_for() {
+        let query = |text: &str| web::Query::<OrderEventsQuery>::from_query(text).unwrap().0;
+
+        let mut broadcaster = broadcast::Broadcaster::new();
+
+        let mut subscribe = |text: &str| broadcaster.subscribe(vec![], query(text).filter(), None);
+
+        let mut bar = subscribe("station=bar&allergy=false");
+        let mut received = subscribe("status=Received");
+
+        let drinks = order("Received", &["bar", "bar"]);
+        let mains = order("Accepted", &["grill", "bar"]);
+
+        assert_eq!(order_tags(&drinks)["station"], ["bar"]);
+        assert_eq!(order_tags(&mains)["station"], ["bar", "grill"]);
+
+        let mut following = subscribe(&format!("order={}", mains.id.unwrap()));
+
+        for order in [&drinks, &mains] {
+            broadcaster.send_tagged("order_created", "{}", &order_tags(order));
+        }
+
+        let count = |rx: &mut tokio::sync::mpsc::Receiver<broadcast::Event>| {
+            std::iter::from_fn(|| rx.try_recv().ok()).count()
+        };
+
+        assert_eq!(count(&mut bar), 2);
+        assert_eq!(count(&mut received), 1);
+        assert_eq!(count(&mut following), 1);
+    }
+
+    #[test]
+    fn tags_orders_leaving_a_status_with_it() {
+        let mut order = order("Ready", &[]);
+
+        let mut broadcaster = broadcast::Broadcaster::new();
+        let filter = HashMap::from([("status".to_string(), "Ready".to_string())]);
+        let mut ready = broadcaster.subscribe(vec![], filter, None);
+
+        order.set_status(OrderStatus::Served);
+        broadcaster.send_tagged("order_updated", "{}", &order_tags(&order));
+
+        assert_eq!(order_tags(&order)["status"], ["Served", "Ready"]);
+        assert_eq!(ready.try_recv().unwrap().name, "order_updated");
+
+        // the next change no longer concerns the kitchen's ready list
+        order.set_status(OrderStatus::Completed);
+        broadcaster.send_tagged("order_updated", "{}", &order_tags(&order));
+
+        assert!(ready.try_recv().is_err());
+    }
 }
\ No newline at end of file
@@ -1,22 +1,18 @@
-extern crate dotenv;
-use dotenv::dotenv;
-use futures::StreamExt;
 use log;
-use mongodb::bson::oid::ObjectId;
-use mongodb::{
-    bson::{doc, extjson::de::Error},
-    results::{DeleteResult, InsertOneResult, UpdateResult},
-    Client, Collection,
-};
-use std::{env, str::FromStr};
+use mongodb::bson::{doc, oid::ObjectId, Bson, Document};
+use std::sync::Arc;
 
 use super::model::*;
+use crate::categories::collection::CategoryCollection;
 use crate::console;
+use crate::ingredients::collection::{IngredientCollection, IngredientCollectionError};
+use crate::money::Money;
+use crate::storage::{self, Filter, Sort, Storage};
 
 #[derive(Clone)]
 pub struct ProductCollection
 {
-    collection_products: Collection<Product>,
+    collection_products: Arc<dyn Storage<Product>>,
 }
 
 #[derive(Debug)]
@@ -25,14 +21,38 @@ pub enum ProductCollectionError
     ProductNameExists,
     ProductNotFound,
     ProductNotMofified,
+    /// The category given for a product does not exist
+    CategoryNotFound,
+    /// An ingredient of the recipe given for a product does not exist
+    IngredientNotFound,
+    /// The product has no recipe
+    RecipeNotFound,
+    /// Modifiers of a product are priced in another currency than the product
+    CurrencyMismatch,
+    /// The product is "86"ed, or fewer than asked for remain
+    ProductUnavailable(Box<Product>),
     CustomError(String),
 }
 
 impl ProductCollection
 {
-    pub async fn init(database: mongodb::Database) -> Self
+    /// Fields the product listing filters and sorts by, each group one index
+    const INDEXES: [&'static [&'static str]; 4] = [
+        &["kind"],
+        &["category", "position"],
+        &["price.currency", "price.amount"],
+        &["name"],
+    ];
+
+    pub async fn init(collection_products: Arc<dyn Storage<Product>>) -> Self
     {
-        let collection_products: Collection<Product> = database.collection("Products");
+        for fields in Self::INDEXES
+        {
+            if let Err(error) = collection_products.index(fields).await
+            {
+                error!("Failed to index products by {:?}. Error: {:?}", fields, error);
+            }
+        }
 
         ProductCollection {
             collection_products,
@@ -42,10 +62,29 @@ impl ProductCollection
     pub async fn create(
         &self,
         content: ProductCreateRequest,
-    ) -> Result<InsertOneResult, ProductCollectionError>
+        collection_categories: &CategoryCollection,
+        collection_ingredients: &IngredientCollection,
+    ) -> Result<Product, ProductCollectionError>
     {
         info!("Creating product...");
 
+        let category = match content.category
+        {
+            Some(category) => Some(Self::category(&category, collection_categories).await?),
+            None => None,
+        };
+
+        if let Some(recipe) = &content.recipe
+        {
+            Self::check_recipe(recipe, collection_ingredients).await?;
+        }
+
+        if !ModifierGroup::all_priced_in(&content.modifiers, content.price.currency())
+        {
+            error!("Product modifiers are priced in another currency.");
+            return Err(ProductCollectionError::CurrencyMismatch);
+        }
+
         let is_exist = self.is_name_exist(content.name.clone()).await;
 
         match is_exist
@@ -57,18 +96,31 @@ impl ProductCollection
             },
             Ok(false) =>
             {
-                let product = Product {
+                let mut product = Product {
                     id: None,
                     name: content.name,
                     price: content.price,
                     kind: content.kind,
+                    station: content.station,
+                    category,
+                    position: content.position.unwrap_or(0),
+                    modifiers: content.modifiers,
+                    available: true,
+                    remaining: None,
+                    recipe: content.recipe,
+                    allergens: distinct(content.allergens),
+                    dietary: distinct(content.dietary),
                 };
 
-                let result = self.collection_products.insert_one(product, None).await;
+                let result = self.collection_products.insert(product.clone()).await;
 
                 match result
                 {
-                    Ok(result) => Ok(result),
+                    Ok(id) =>
+                    {
+                        product.id = Some(id);
+                        Ok(product)
+                    },
                     Err(_) => Err(ProductCollectionError::CustomError(
                         "Failed to create product.".to_string(),
                     )),
@@ -84,73 +136,170 @@ impl ProductCollection
         }
     }
 
+    /// Lists the products matching `filter`, in the order of `sort` or as they were added
     pub async fn list(
         &self,
+        filter: &Filter,
+        sort: Option<&Sort>,
         offset: u64,
         limit: i64,
     ) -> Result<Vec<Product>, ProductCollectionError>
     {
         info!("Listing product...");
 
-        let find_options = mongodb::options::FindOptions::builder()
-            .skip(offset)
-            .limit(limit)
-            .build();
-
-        let mut products_cursor = self
-            .collection_products
-            .find(None, find_options)
-            .await
-            .ok()
-            .expect("Failed to execute find.");
-
-        let mut products_list: Vec<Product> = Vec::new();
+        let result = self.collection_products.find_sorted(filter, sort, offset, limit).await;
 
-        while let Some(product) = products_cursor.next().await
+        match result
         {
-            products_list.push(product.unwrap());
+            Ok(products_list) =>
+            {
+                info!("Listed product...");
+                Ok(products_list)
+            },
+            Err(error) =>
+            {
+                error!("Failed to list products. Error: {:?}", error);
+                Err(ProductCollectionError::CustomError(
+                    "Failed to list products.".to_string(),
+                ))
+            },
         }
-
-        info!("Listed product...");
-
-        Ok(products_list)
     }
 
+    /// Changes the fields given in `content`, leaving the others as they are,
+    /// and returns the updated product
     pub async fn update(
         &self,
-        req_id: String,
+        id: ObjectId,
         content: ProductUpdateRequest,
-    ) -> Result<UpdateResult, ProductCollectionError>
+        collection_categories: &CategoryCollection,
+        collection_ingredients: &IngredientCollection,
+    ) -> Result<Product, ProductCollectionError>
     {
         info!("Updating product...");
 
-        let id = ObjectId::from_str(&req_id).unwrap();
+        let product = self.get(id).await?;
+
+        let to_custom_error = |error: storage::StorageError| {
+            ProductCollectionError::CustomError(
+                format!("Failed to update product. Error: {:?}", error).to_string(),
+            )
+        };
+
+        let mut update = Document::new();
+
+        if let Some(name) = content.name
+        {
+            if name != product.name && self.is_name_exist(name.clone()).await?
+            {
+                error!("Product name already exist.");
+                return Err(ProductCollectionError::ProductNameExists);
+            }
+
+            update.insert("name", name);
+        }
+
+        let price = content.price.unwrap_or(product.price);
+        let modifiers = content.modifiers.as_ref().unwrap_or(&product.modifiers);
+
+        if !ModifierGroup::all_priced_in(modifiers, price.currency())
+        {
+            error!("Product modifiers are priced in another currency.");
+            return Err(ProductCollectionError::CurrencyMismatch);
+        }
+
+        if let Some(price) = content.price
+        {
+            update.insert("price", storage::to_bson(&price).map_err(to_custom_error)?);
+        }
+
+        if let Some(modifiers) = &content.modifiers
+        {
+            update.insert("modifiers", storage::to_bson(modifiers).map_err(to_custom_error)?);
+        }
+
+        if let Some(kind) = content.kind
+        {
+            update.insert("kind", storage::to_bson(&kind).map_err(to_custom_error)?);
+        }
+
+        if let Some(station) = content.station
+        {
+            update.insert("station", station);
+        }
+
+        match content.category
+        {
+            Some(Some(category)) =>
+            {
+                update.insert("category", Self::category(&category, collection_categories).await?);
+            },
+            Some(None) =>
+            {
+                update.insert("category", Bson::Null);
+            },
+            None => (),
+        }
+
+        if let Some(position) = content.position
+        {
+            update.insert("position", position);
+        }
+
+        if let Some(allergens) = content.allergens
+        {
+            let allergens = storage::to_bson(&distinct(allergens)).map_err(to_custom_error)?;
+
+            update.insert("allergens", allergens);
+        }
+
+        if let Some(dietary) = content.dietary
+        {
+            let dietary = storage::to_bson(&distinct(dietary)).map_err(to_custom_error)?;
+
+            update.insert("dietary", dietary);
+        }
+
+        match content.recipe
+        {
+            Some(Some(recipe)) =>
+            {
+                Self::check_recipe(&recipe, collection_ingredients).await?;
+
+                update.insert("recipe", storage::to_bson(&recipe).map_err(to_custom_error)?);
+            },
+            Some(None) =>
+            {
+                update.insert("recipe", Bson::Null);
+            },
+            None => (),
+        }
 
-        let filter = doc! { "_id": id };
-        let update = doc! { "$set": { "name": content.name, "price": content.price } };
+        if update.is_empty()
+        {
+            error!("Product not modified.");
+            return Err(ProductCollectionError::ProductNotMofified);
+        }
 
-        let result = self
-            .collection_products
-            .update_one(filter, update, None)
-            .await;
+        let result = self.collection_products.update(&id, update).await;
 
         match result
         {
             Ok(result) =>
             {
-                if result.matched_count == 0
+                if !result.matched
                 {
                     error!("Product not found.");
                     return Err(ProductCollectionError::ProductNotFound);
                 }
 
-                if result.modified_count == 0
+                if !result.modified
                 {
                     error!("Product not modified.");
                     return Err(ProductCollectionError::ProductNotMofified);
                 }
 
-                Ok(result)
+                self.get(id).await
             },
             Err(error) =>
             {
@@ -162,15 +311,11 @@ impl ProductCollection
         }
     }
 
-    pub async fn get(&self, req_id: String) -> Result<Product, ProductCollectionError>
+    pub async fn get(&self, id: ObjectId) -> Result<Product, ProductCollectionError>
     {
         info!("Getting product by id...");
 
-        let id = ObjectId::from_str(&req_id).unwrap();
-
-        let filter = doc! { "_id": id };
-
-        let result = self.collection_products.find_one(filter, None).await;
+        let result = self.collection_products.get(&id).await;
 
         match result
         {
@@ -190,19 +335,72 @@ impl ProductCollection
         }
     }
 
-    pub async fn delete(&self, req_id: String) -> Result<DeleteResult, ProductCollectionError>
+    /// The recipe of a product, with the name and unit of each ingredient
+    pub async fn recipe(
+        &self,
+        id: ObjectId,
+        collection_ingredients: &IngredientCollection,
+    ) -> Result<RecipeView, ProductCollectionError>
     {
-        info!("Deleting product by id...");
+        info!("Getting product recipe...");
 
-        let id = ObjectId::from_str(&req_id).unwrap();
+        let product = self.get(id).await?;
 
-        let filter = doc! { "_id": id };
+        let recipe = match product.recipe
+        {
+            Some(recipe) => recipe,
+            None =>
+            {
+                error!("Product has no recipe.");
+                return Err(ProductCollectionError::RecipeNotFound);
+            },
+        };
+
+        let mut ingredients = Vec::new();
+
+        for amount in recipe.ingredients
+        {
+            let ingredient = match collection_ingredients.get(amount.ingredient).await
+            {
+                Ok(ingredient) => Some(ingredient),
+                Err(IngredientCollectionError::IngredientNotFound) => None,
+                Err(error) =>
+                {
+                    return Err(ProductCollectionError::CustomError(format!("{:?}", error)))
+                },
+            };
+
+            ingredients.push(RecipeIngredient {
+                ingredient: amount.ingredient,
+                name: ingredient.as_ref().map(|ingredient| ingredient.name.clone()),
+                unit: ingredient.map(|ingredient| ingredient.unit),
+                quantity: amount.quantity,
+            });
+        }
+
+        Ok(RecipeView {
+            product: id,
+            name: product.name,
+            steps: recipe.steps,
+            ingredients,
+            plating: recipe.plating,
+            prep_minutes: recipe.prep_minutes,
+        })
+    }
+
+    /// Deletes a product, returning it as it was
+    pub async fn delete(&self, id: ObjectId) -> Result<Product, ProductCollectionError>
+    {
+        info!("Deleting product by id...");
 
-        let result = self.collection_products.delete_one(filter, None).await;
+        let product = self.get(id).await?;
+
+        let result = self.collection_products.delete(&id).await;
 
         match result
         {
-            Ok(result) => Ok(result),
+            Ok(true) => Ok(product),
+            Ok(false) => Err(ProductCollectionError::ProductNotFound),
             Err(_) =>
             {
                 error!("Failed to delete product by id.");
@@ -213,13 +411,224 @@ impl ProductCollection
         }
     }
 
+    /// Lists the products that cannot be ordered right now, the "86" list
+    pub async fn list_unavailable(&self) -> Result<Vec<Product>, ProductCollectionError>
+    {
+        info!("Listing unavailable products...");
+
+        self.list(&Product::availability_filter(false), None, 0, 0).await
+    }
+
+    /// Changes whether a product is available and how many remain, returning the product
+    pub async fn set_availability(
+        &self,
+        id: ObjectId,
+        content: AvailabilityUpdateRequest,
+    ) -> Result<Product, ProductCollectionError>
+    {
+        info!("Updating product availability...");
+
+        self.get(id).await?;
+
+        let mut update = Document::new();
+
+        if let Some(available) = content.available
+        {
+            update.insert("available", available);
+        }
+
+        if let Some(remaining) = content.remaining
+        {
+            update.insert("remaining", Self::remaining_bson(remaining)?);
+        }
+
+        let result = self.collection_products.update(&id, update).await;
+
+        match result
+        {
+            Ok(result) if !result.matched => Err(ProductCollectionError::ProductNotFound),
+            Ok(result) if !result.modified => Err(ProductCollectionError::ProductNotMofified),
+            Ok(_) => self.get(id).await,
+            Err(error) =>
+            {
+                error!("Failed to update product availability. Error: {:?}", error);
+                Err(ProductCollectionError::CustomError(format!(
+                    "Failed to update product availability. Error: {:?}",
+                    error
+                )))
+            },
+        }
+    }
+
+    /// Takes `quantity` from the remaining count of a product, when it has one
+    ///
+    /// Returns the product as it is afterwards if its count changed.
+    pub async fn reserve(
+        &self,
+        id: ObjectId,
+        quantity: u32,
+    ) -> Result<Option<Product>, ProductCollectionError>
+    {
+        self.change_remaining(id, |product| match product.remaining
+        {
+            _ if !product.is_available() => None,
+            None => Some(None),
+            Some(remaining) => remaining.checked_sub(quantity).map(Some),
+        })
+        .await
+    }
+
+    /// Gives back `quantity` taken by `reserve`, e.g. when the order could not be placed
+    pub async fn release(
+        &self,
+        id: ObjectId,
+        quantity: u32,
+    ) -> Result<Option<Product>, ProductCollectionError>
+    {
+        self.change_remaining(id, |product| {
+            Some(product.remaining.map(|remaining| remaining.saturating_add(quantity)))
+        })
+        .await
+    }
+
+    /// Sets the remaining count to what `change` makes of the current product, retrying
+    /// when another order changed it in between
+    ///
+    /// `change` returns `None` when the product cannot be ordered, and `Some(None)`
+    /// when the product has no count to change.
+    async fn change_remaining(
+        &self,
+        id: ObjectId,
+        change: impl Fn(&Product) -> Option<Option<u32>>,
+    ) -> Result<Option<Product>, ProductCollectionError>
+    {
+        const ATTEMPTS: usize = 10;
+
+        for _ in 0..ATTEMPTS
+        {
+            let mut product = self.get(id).await?;
+
+            let remaining = match change(&product)
+            {
+                Some(Some(remaining)) => remaining,
+                Some(None) => return Ok(None),
+                None => return Err(ProductCollectionError::ProductUnavailable(Box::new(product))),
+            };
+
+            let expected = doc! { "remaining": Self::remaining_bson(product.remaining)? };
+            let update = doc! { "remaining": Self::remaining_bson(Some(remaining))? };
+
+            let result = self.collection_products.update_if(&id, expected, update).await;
+
+            match result
+            {
+                Ok(result) if result.modified =>
+                {
+                    product.remaining = Some(remaining);
+                    return Ok(Some(product));
+                },
+                Ok(_) => continue,
+                Err(error) => return Err(ProductCollectionError::CustomError(error.to_string())),
+            }
+        }
+
+        Err(ProductCollectionError::CustomError(
+            "Remaining count kept changing, giving up.".to_string(),
+        ))
+    }
+
+    fn remaining_bson(remaining: Option<u32>) -> Result<Bson, ProductCollectionError>
+    {
+        storage::to_bson(&remaining)
+            .map_err(|error| ProductCollectionError::CustomError(error.to_string()))
+    }
+
+    /// Lists the products in a category, not counting its subcategories
+    pub async fn list_in_category(
+        &self,
+        category: ObjectId,
+    ) -> Result<Vec<Product>, ProductCollectionError>
+    {
+        let result = self.collection_products.find(&Filter::eq("category", category), 0, 0).await;
+
+        result.map_err(|error| {
+            error!("Failed to list products in category. Error: {:?}", error);
+            ProductCollectionError::CustomError(
+                format!("Failed to list products in category. Error: {:?}", error).to_string(),
+            )
+        })
+    }
+
+    /// Lists the products whose recipe takes the ingredient
+    pub async fn list_using_ingredient(
+        &self,
+        ingredient: ObjectId,
+    ) -> Result<Vec<Product>, ProductCollectionError>
+    {
+        let products = self.list(&Filter::All, None, 0, 0).await?;
+
+        Ok(products
+            .into_iter()
+            .filter(|product| {
+                product.recipe.as_ref().is_some_and(|recipe| {
+                    recipe.ingredients.iter().any(|amount| amount.ingredient == ingredient)
+                })
+            })
+            .collect())
+    }
+
+    /// Checks that every ingredient of a recipe exists
+    async fn check_recipe(
+        recipe: &Recipe,
+        collection_ingredients: &IngredientCollection,
+    ) -> Result<(), ProductCollectionError>
+    {
+        for amount in &recipe.ingredients
+        {
+            match collection_ingredients.exists(amount.ingredient).await
+            {
+                Ok(true) => (),
+                Ok(false) =>
+                {
+                    error!("Recipe ingredient not found.");
+                    return Err(ProductCollectionError::IngredientNotFound);
+                },
+                Err(error) =>
+                {
+                    return Err(ProductCollectionError::CustomError(format!("{:?}", error)))
+                },
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Parses and checks the category given for a product
+    async fn category(
+        category: &str,
+        collection_categories: &CategoryCollection,
+    ) -> Result<ObjectId, ProductCollectionError>
+    {
+        let category = ObjectId::parse_str(category)
+            .map_err(|_| ProductCollectionError::CategoryNotFound)?;
+
+        match collection_categories.exists(category).await
+        {
+            Ok(true) => Ok(category),
+            Ok(false) =>
+            {
+                error!("Product category not found.");
+                Err(ProductCollectionError::CategoryNotFound)
+            },
+            Err(error) => Err(ProductCollectionError::CustomError(format!("{:?}", error))),
+        }
+    }
+
     pub async fn is_name_exist(&self, name: String) -> Result<bool, ProductCollectionError>
     {
         info!("Checking if product name is exist...");
 
-        let filter = doc! { "name": name };
-
-        let product = self.collection_products.find_one(filter, None).await;
+        let product = self.collection_products.find_one(&Filter::eq("name", name)).await;
 
         match product
         {
@@ -235,32 +644,242 @@ impl ProductCollection
         }
     }
 
-    pub async fn get_product_price(&self, req_id: String) -> Result<f32, ProductCollectionError>
+    /// Rewrites prices stored before `Money` as exact amounts, returning how many changed
+    ///
+    /// Old prices are plain numbers in `CURRENCY`; running it again changes nothing.
+    pub async fn migrate_prices(&self) -> Result<usize, ProductCollectionError>
     {
-        info!("Getting product price by id...");
+        info!("Migrating product prices...");
 
-        let id = ObjectId::from_str(&req_id).unwrap();
+        let to_custom_error =
+            |error: storage::StorageError| ProductCollectionError::CustomError(error.to_string());
 
-        let filter = doc! { "_id": id };
+        let products = self.collection_products.list(0, 0).await.map_err(to_custom_error)?;
 
-        let result = self.collection_products.find_one(filter, None).await;
+        let mut migrated = 0;
 
-        match result
+        for product in products
         {
-            Ok(Some(product)) => Ok(product.price),
-            Ok(None) =>
+            let id = match product.id
             {
-                error!("Product not found.");
-                Err(ProductCollectionError::ProductNotFound)
-            },
-            Err(error) =>
+                Some(id) => id,
+                None => continue,
+            };
+
+            let price = storage::to_bson(&product.price).map_err(to_custom_error)?;
+
+            let result = self
+                .collection_products
+                .update(&id, doc! { "price": price })
+                .await
+                .map_err(to_custom_error)?;
+
+            if result.modified
             {
-                error!("Failed to get product price by id. Error: {:?}", error);
-                Err(ProductCollectionError::CustomError(
-                    format!("Failed to get product price by id. Error: {:?}", error).to_string(),
-                ))
-            },
-            
+                migrated += 1;
+            }
         }
+
+        Ok(migrated)
     }
 }
+
+/// Sorts labels such as allergens, dropping any given twice
+fn distinct<T: Ord>(mut labels: Vec<T>) -> Vec<T>
+{
+    labels.sort_unstable();
+    labels.dedup();
+    labels
+}
+
+#[cfg(test)]
+mod tests
+{
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::database::tests::{memory, product};
+    use crate::database::Database;
+    use crate::ingredients::model::Unit;
+
+    async fn update(
+        database: &Database,
+        id: ObjectId,
+        content: Value,
+    ) -> Result<Product, ProductCollectionError>
+    {
+        let content = serde_json::from_value(content).unwrap();
+
+        database
+            .products()
+            .await
+            .update(id, content, database.categories().await, database.ingredients().await)
+            .await
+    }
+
+    fn soup() -> Value
+    {
+        json!({
+            "name": "Soup",
+            "price": "4.50 EUR",
+            "kind": "Food",
+            "station": "stove",
+            "allergens": ["celery"],
+            "modifiers": [{ "name": "Bread", "options": [{ "name": "Rye", "price": "1.00 EUR" }] }],
+        })
+    }
+
+    #[actix_web::test]
+    async fn changes_only_the_fields_given()
+    {
+        let database = memory().await;
+
+        let soup = product(&database, soup()).await;
+        let id = soup.id.unwrap();
+
+        let updated = update(&database, id, json!({ "price": "5.00 EUR" })).await.unwrap();
+
+        assert_eq!(updated.price.to_string(), "5.00 EUR");
+        assert_eq!(updated.name, "Soup");
+        assert_eq!(updated.station.as_deref(), Some("stove"));
+        assert_eq!(updated.allergens, soup.allergens);
+        assert_eq!(updated.modifiers.len(), 1);
+
+        let allergens = json!({ "allergens": ["mustard", "celery", "mustard"], "position": 2 });
+        let updated = update(&database, id, allergens).await.unwrap();
+
+        assert_eq!(updated.allergens, [Allergen::Celery, Allergen::Mustard]);
+        assert_eq!((updated.position, updated.price), (2, "5.00 EUR".parse().unwrap()));
+    }
+
+    #[actix_web::test]
+    async fn rejects_updates_that_change_nothing_or_clash()
+    {
+        let database = memory().await;
+
+        let id = product(&database, soup()).await.id.unwrap();
+        product(&database, json!({ "name": "Stew", "price": "9.00 EUR", "kind": "Food" })).await;
+
+        let rejected = [
+            (json!({}), "ProductNotMofified"),
+            (json!({ "name": "Soup", "price": "4.50 EUR" }), "ProductNotMofified"),
+            (json!({ "name": "Stew" }), "ProductNameExists"),
+            (json!({ "price": "4.50 USD" }), "CurrencyMismatch"),
+        ];
+
+        for (content, expected) in rejected
+        {
+            let error = update(&database, id, content.clone()).await.unwrap_err();
+
+            assert_eq!(format!("{:?}", error), expected, "{}", content);
+        }
+
+        let missing = update(&database, ObjectId::new(), json!({ "name": "Broth" })).await;
+
+        assert!(matches!(missing, Err(ProductCollectionError::ProductNotFound)));
+    }
+
+    #[actix_web::test]
+    async fn reserves_and_releases_what_remains()
+    {
+        let database = memory().await;
+        let products = database.products().await;
+
+        let id = product(&database, soup()).await.id.unwrap();
+        let availability = |content: Value| {
+            products.set_availability(id, serde_json::from_value(content).unwrap())
+        };
+
+        // without a count, orders take nothing
+        assert!(products.reserve(id, 50).await.unwrap().is_none());
+
+        availability(json!({ "remaining": 3 })).await.unwrap();
+
+        let reserved = products.reserve(id, 2).await.unwrap().unwrap();
+
+        assert_eq!(reserved.remaining, Some(1));
+
+        match products.reserve(id, 2).await
+        {
+            Err(ProductCollectionError::ProductUnavailable(product)) =>
+            {
+                assert_eq!(product.remaining, Some(1))
+            },
+            other => panic!("expected the product to be unavailable, got {:?}", other),
+        }
+
+        products.reserve(id, 1).await.unwrap();
+
+        let unavailable = products.list_unavailable().await.unwrap();
+
+        assert_eq!(unavailable.len(), 1);
+        assert!(products.reserve(id, 1).await.is_err());
+
+        let released = products.release(id, 2).await.unwrap().unwrap();
+
+        assert_eq!(released.remaining, Some(2));
+        assert!(products.list_unavailable().await.unwrap().is_empty());
+
+        // an 86ed product cannot be ordered whatever remains, until it is back
+        availability(json!({ "available": false })).await.unwrap();
+
+        assert!(products.reserve(id, 1).await.is_err());
+        assert_eq!(products.list_unavailable().await.unwrap().len(), 1);
+
+        let product = availability(json!({ "available": true, "remaining": null })).await.unwrap();
+
+        assert_eq!((product.available, product.remaining), (true, None));
+        assert!(products.reserve(id, 1).await.unwrap().is_none());
+    }
+
+    #[actix_web::test]
+    async fn shows_recipes_with_their_ingredients()
+    {
+        let database = memory().await;
+        let (products, ingredients) = (database.products().await, database.ingredients().await);
+
+        let content = json!({ "name": "Leek", "unit": "g", "on_hand": 500.0 });
+        let leek = ingredients.create(serde_json::from_value(content).unwrap()).await;
+        let leek = leek.unwrap().id.unwrap();
+
+        let id = product(&database, soup()).await.id.unwrap();
+
+        assert!(matches!(
+            products.recipe(id, ingredients).await,
+            Err(ProductCollectionError::RecipeNotFound)
+        ));
+
+        let recipe = json!({
+            "steps": [{ "text": "Sweat the leeks" }, { "text": "Add the stock" }],
+            "ingredients": [{ "ingredient": leek, "quantity": 120.0 }],
+            "plating": "Deep bowl",
+        });
+
+        update(&database, id, json!({ "recipe": recipe })).await.unwrap();
+
+        let view = products.recipe(id, ingredients).await.unwrap();
+
+        assert_eq!((view.product, view.name.as_str()), (id, "Soup"));
+        assert_eq!(view.steps.len(), 2);
+        assert_eq!(view.steps[0].text, "Sweat the leeks");
+        assert_eq!(view.ingredients.len(), 1);
+        assert_eq!(view.ingredients[0].name.as_deref(), Some("Leek"));
+        assert_eq!(view.ingredients[0].unit, Some(Unit::Gram));
+        assert_eq!(view.ingredients[0].quantity, 120.0);
+        assert_eq!(view.plating.as_deref(), Some("Deep bowl"));
+
+        let unknown = json!({
+            "ingredients": [{ "ingredient": ObjectId::new(), "quantity": 1.0 }],
+        });
+
+        assert!(matches!(
+            update(&database, id, json!({ "recipe": unknown })).await,
+            Err(ProductCollectionError::IngredientNotFound)
+        ));
+
+        let updated = update(&database, id, json!({ "recipe": null })).await.unwrap();
+
+        assert!(updated.recipe.is_none());
+        assert!(products.recipe(id, ingredients).await.is_err());
+    }
+}
\ No newline at end of file
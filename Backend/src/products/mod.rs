@@ -13,10 +13,23 @@ pub fn config(config: &mut web::ServiceConfig)
             .route(web::post().to(service::create))
             .route(web::get().to(service::list))
         )
+        .service(
+            web::resource("/unavailable")
+            .route(web::get().to(service::list_unavailable))
+        )
+        .service(
+            web::resource("/{id}/recipe")
+            .route(web::get().to(service::recipe))
+        )
+        .service(
+            web::resource("/{id}/availability")
+            .route(web::put().to(service::set_availability))
+        )
         .service(
             web::resource("/{id}")
             .route(web::get().to(service::get))
             .route(web::put().to(service::update))
+            .route(web::patch().to(service::update))
             .route(web::delete().to(service::delete))
         )
     );
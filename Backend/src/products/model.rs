@@ -1,7 +1,14 @@
-use mongodb::bson::oid::ObjectId;
+use mongodb::bson::{oid::ObjectId, Bson};
 use serde::{Serialize, Deserialize};
+use validator::Validate;
 
-#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+use crate::common_model;
+use crate::ingredients::model::{IngredientAmount, Unit};
+use crate::money::{Currency, Money};
+use crate::storage::{self, Entity, Filter, Sort, SortOrder};
+use crate::validation;
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
 pub enum ProductKind {
     Food = 0,
     Coctail,
@@ -9,13 +16,237 @@ pub enum ProductKind {
     Other,
 }
 
+/// The allergens a product can be marked with
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Allergen
+{
+    Gluten,
+    Dairy,
+    Eggs,
+    Fish,
+    Shellfish,
+    Molluscs,
+    Peanuts,
+    Nuts,
+    Soy,
+    Sesame,
+    Celery,
+    Mustard,
+    Sulphites,
+    Lupin,
+}
+
+/// The diets a product is labelled as suitable for
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum DietaryTag
+{
+    Vegetarian,
+    Vegan,
+    Halal,
+    Kosher,
+    GlutenFree,
+    DairyFree,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Product {
     #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
     pub id: Option<ObjectId>,
     pub name: String,
-    pub price: f32,
+    pub price: Money,
     pub kind: ProductKind,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub station: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub category: Option<ObjectId>,
+    /// Where the product is shown within its category, lowest first
+    #[serde(default)]
+    pub position: i32,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub modifiers: Vec<ModifierGroup>,
+    /// Cleared to "86" the product when the kitchen runs out of it
+    #[serde(default = "available")]
+    pub available: bool,
+    /// How many more can be ordered, counting down with each order; no limit when left out
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remaining: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recipe: Option<Recipe>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allergens: Vec<Allergen>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dietary: Vec<DietaryTag>,
+}
+
+fn available() -> bool
+{
+    true
+}
+
+impl Product
+{
+    /// Whether the product can be ordered at all
+    pub fn is_available(&self) -> bool
+    {
+        self.available && self.remaining != Some(0)
+    }
+
+    /// The storage filter selecting the products that can (`true`) or cannot (`false`)
+    /// be ordered, agreeing with `is_available`
+    pub fn availability_filter(available: bool) -> Filter
+    {
+        // products stored before availability was kept have no `available` field
+        match available
+        {
+            true => Filter::none_of("available", vec![false.into()])
+                .and(Filter::none_of("remaining", vec![0.into()])),
+            false => Filter::eq("available", false).or(Filter::eq("remaining", 0)),
+        }
+    }
+}
+
+impl Entity for Product
+{
+    fn id(&self) -> Option<ObjectId>
+    {
+        self.id
+    }
+
+    fn set_id(&mut self, id: ObjectId)
+    {
+        self.id = Some(id);
+    }
+}
+
+/// A choice offered with a product, e.g. Size or Extras
+///
+/// A required group needs at least one option, and at least `min` when that is
+/// higher; an optional group may be left out, but otherwise takes at least `min`.
+#[derive(Debug, Serialize, Deserialize, Clone, Validate)]
+#[validate(schema(function = "validation::modifier_group"))]
+pub struct ModifierGroup
+{
+    #[validate(
+        length(max = 100, message = "must be at most 100 characters"),
+        custom = "validation::not_blank"
+    )]
+    pub name: String,
+    #[serde(default)]
+    pub required: bool,
+    #[serde(default)]
+    pub min: u32,
+    /// No limit when left out
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max: Option<u32>,
+    #[validate(length(min = 1, message = "must contain at least one option"))]
+    #[validate]
+    pub options: Vec<ModifierOption>,
+}
+
+impl ModifierGroup
+{
+    /// Whether every priced option of `groups` is priced in `currency`
+    pub fn all_priced_in(groups: &[ModifierGroup], currency: Currency) -> bool
+    {
+        groups
+            .iter()
+            .flat_map(|group| &group.options)
+            .filter_map(|option| option.price)
+            .all(|price| price.currency() == currency)
+    }
+
+    /// The fewest options that may be chosen, when any are
+    pub fn min_choices(&self) -> u32
+    {
+        match self.required
+        {
+            true => self.min.max(1),
+            false => self.min,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Validate)]
+pub struct ModifierOption
+{
+    #[validate(
+        length(max = 100, message = "must be at most 100 characters"),
+        custom = "validation::not_blank"
+    )]
+    pub name: String,
+    /// Added to the price of the product, negative for a discount
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub price: Option<Money>,
+}
+
+/// How a product is made
+#[derive(Debug, Serialize, Deserialize, Clone, Validate)]
+pub struct Recipe
+{
+    /// The steps in the order they are done
+    #[serde(default)]
+    #[validate]
+    pub steps: Vec<RecipeStep>,
+    /// What one of the product takes from stock
+    #[serde(default)]
+    #[validate]
+    pub ingredients: Vec<IngredientAmount>,
+    /// How the product is plated or served
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[validate(
+        length(max = 1000, message = "must be at most 1000 characters"),
+        custom = "validation::not_blank"
+    )]
+    pub plating: Option<String>,
+    /// How long making one should take, in minutes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[validate(range(min = 1, message = "must be at least 1"))]
+    pub prep_minutes: Option<u32>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Validate)]
+pub struct RecipeStep
+{
+    #[validate(
+        length(max = 500, message = "must be at most 500 characters"),
+        custom = "validation::not_blank"
+    )]
+    pub text: String,
+}
+
+/// The recipe of a product as shown to cooks, with the name and unit of each ingredient
+#[derive(Debug, Serialize, Clone)]
+pub struct RecipeView
+{
+    pub product: ObjectId,
+    pub name: String,
+    pub steps: Vec<RecipeStep>,
+    pub ingredients: Vec<RecipeIngredient>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub plating: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prep_minutes: Option<u32>,
+}
+
+/// An ingredient of a `RecipeView`, without a name or unit if it no longer exists
+#[derive(Debug, Serialize, Clone)]
+pub struct RecipeIngredient
+{
+    pub ingredient: ObjectId,
+    pub name: Option<String>,
+    pub unit: Option<Unit>,
+    pub quantity: f64,
+}
+
+/// Marks a product as available or not, and sets or, with `null`, clears how many remain
+#[derive(Debug, Serialize, Deserialize, Clone, Validate)]
+pub struct AvailabilityUpdateRequest
+{
+    pub available: Option<bool>,
+    #[serde(default, deserialize_with = "common_model::deserialize_some")]
+    pub remaining: Option<Option<u32>>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -23,20 +254,180 @@ pub struct ProductListQuery
 {
     pub offset: Option<u64>,
     pub limit: Option<i64>,
+    pub kind: Option<ProductKind>,
+    pub category: Option<ObjectId>,
+    /// Only products costing this or more, in the same currency
+    pub min_price: Option<Money>,
+    /// Only products costing this or less, in the same currency
+    pub max_price: Option<Money>,
+    /// Only products that can (`true`) or cannot (`false`) be ordered right now
+    pub available: Option<bool>,
+    /// Only products with this in their name, ignoring case
+    pub search: Option<String>,
+    pub sort: Option<ProductSort>,
+    /// Lowest first unless `desc`
+    pub order: Option<SortOrder>,
+    /// Leaves out products with any of these allergens, e.g. `nuts,gluten`
+    #[serde(default, deserialize_with = "common_model::comma_separated")]
+    pub exclude_allergen: Vec<Allergen>,
+    /// Only products labelled with all of these diets, e.g. `vegan`
+    #[serde(default, deserialize_with = "common_model::comma_separated")]
+    pub dietary: Vec<DietaryTag>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+/// What a product listing can be sorted by
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ProductSort
+{
+    Name,
+    Price,
+}
+
+impl ProductSort
+{
+    fn field(&self) -> &'static str
+    {
+        match self
+        {
+            ProductSort::Name => "name",
+            ProductSort::Price => "price.amount",
+        }
+    }
+}
+
+impl ProductListQuery
+{
+    /// The storage filter selecting the products asked for
+    pub fn filter(&self) -> Filter
+    {
+        let mut filter = Filter::All;
+
+        if let Some(kind) = &self.kind
+        {
+            filter = filter.and(Filter::eq("kind", stored(kind)));
+        }
+
+        if let Some(category) = self.category
+        {
+            filter = filter.and(Filter::eq("category", category));
+        }
+
+        if let Some(min_price) = self.min_price
+        {
+            filter = filter
+                .and(Filter::eq("price.currency", min_price.currency().to_string()))
+                .and(Filter::at_least("price.amount", min_price.minor()));
+        }
+
+        if let Some(max_price) = self.max_price
+        {
+            filter = filter
+                .and(Filter::eq("price.currency", max_price.currency().to_string()))
+                .and(Filter::at_most("price.amount", max_price.minor()));
+        }
+
+        if let Some(available) = self.available
+        {
+            filter = filter.and(Product::availability_filter(available));
+        }
+
+        if let Some(search) = self.search.as_deref().map(str::trim).filter(|text| !text.is_empty())
+        {
+            filter = filter.and(Filter::contains("name", search));
+        }
+
+        if !self.exclude_allergen.is_empty()
+        {
+            let allergens = self.exclude_allergen.iter().map(stored).collect();
+
+            filter = filter.and(Filter::none_of("allergens", allergens));
+        }
+
+        for tag in &self.dietary
+        {
+            filter = filter.and(Filter::eq("dietary", stored(tag)));
+        }
+
+        filter
+    }
+
+    /// The order asked for, if any
+    pub fn sort(&self) -> Option<Sort>
+    {
+        self.sort.map(|sort| {
+            let order = Sort::new(sort.field(), self.order.unwrap_or(SortOrder::Asc));
+
+            match sort
+            {
+                // "apple pie" belongs between "Burger" and "Cheeseburger" on a menu
+                ProductSort::Name => order.ignoring_case(),
+                ProductSort::Price => order,
+            }
+        })
+    }
+}
+
+// the stored form of a label such as a kind or an allergen
+fn stored<T: Serialize>(label: &T) -> Bson
+{
+    storage::to_bson(label).unwrap_or(Bson::Null)
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Validate)]
 pub struct ProductCreateRequest
 {
+    #[validate(
+        length(max = 100, message = "must be at most 100 characters"),
+        custom = "validation::not_blank"
+    )]
     pub name: String,
-    pub price: f32,
+    #[validate(custom = "validation::price")]
+    pub price: Money,
     pub kind: ProductKind,
+    #[validate(custom = "validation::not_blank")]
+    pub station: Option<String>,
+    #[validate(custom = "validation::object_id")]
+    pub category: Option<String>,
+    pub position: Option<i32>,
+    #[serde(default)]
+    #[validate]
+    pub modifiers: Vec<ModifierGroup>,
+    #[validate]
+    pub recipe: Option<Recipe>,
+    #[serde(default)]
+    pub allergens: Vec<Allergen>,
+    #[serde(default)]
+    pub dietary: Vec<DietaryTag>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, Validate)]
 pub struct ProductUpdateRequest
 {
+    #[validate(
+        length(max = 100, message = "must be at most 100 characters"),
+        custom = "validation::not_blank"
+    )]
     pub name: Option<String>,
-    pub price: Option<f32>,
+    #[validate(custom = "validation::price")]
+    pub price: Option<Money>,
     pub kind: Option<ProductKind>,
+    #[validate(custom = "validation::not_blank")]
+    pub station: Option<String>,
+    /// `null` takes the product out of its category
+    #[serde(default, deserialize_with = "common_model::deserialize_some")]
+    #[validate(custom = "validation::object_id")]
+    pub category: Option<Option<String>>,
+    pub position: Option<i32>,
+    /// Replaces all modifier groups of the product
+    #[validate]
+    pub modifiers: Option<Vec<ModifierGroup>>,
+    /// `null` removes the recipe
+    #[serde(default, deserialize_with = "common_model::deserialize_some")]
+    #[validate]
+    pub recipe: Option<Option<Recipe>>,
+    /// Replaces all allergens of the product
+    pub allergens: Option<Vec<Allergen>>,
+    /// Replaces all dietary tags of the product
+    pub dietary: Option<Vec<DietaryTag>>,
 }
\ No newline at end of file
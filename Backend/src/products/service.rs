@@ -1,66 +1,46 @@
 use super::collection::*;
 use super::model::*;
+use crate::broadcast::Broadcaster;
 use crate::common_model::CommonResponse;
 use crate::console;
 use crate::database::Database;
+use crate::error::ApiError;
+use crate::extract::{EntityId, ValidJson};
+use crate::events::{Change, DomainEvent};
 use actix_web::Responder;
 use actix_web::{web, HttpResponse};
 use env_logger::{Builder, Env};
 use log;
 use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
 
 pub async fn create(
     database_data: web::Data<Database>,
-    content: web::Json<ProductCreateRequest>,
-) -> impl Responder
+    broadcaster: web::Data<Mutex<Broadcaster>>,
+    content: ValidJson<ProductCreateRequest>,
+) -> Result<HttpResponse, ApiError>
 {
     info!("Create Product requested...");
 
     let collection = database_data.products().await;
+    let collection_categories = database_data.categories().await;
+    let collection_ingredients = database_data.ingredients().await;
 
-    let insertion_result = collection.create(content.into_inner()).await;
-
-    match insertion_result
-    {
-        Ok(result) =>
-        {
-            let inserted_data = result.inserted_id;
-            HttpResponse::Ok().json(inserted_data)
-        },
-        Err(error) => match error
-        {
-            ProductCollectionError::ProductNameExists =>
-            {
-                let response = CommonResponse::<Product> {
-                    message: "Product name already exist.".to_string(),
-                    data: None,
-                };
-                HttpResponse::BadRequest().json(response)
-            },
-            ProductCollectionError::CustomError(message) =>
-            {
-                let response = CommonResponse::<Product> {
-                    message,
-                    data: None,
-                };
-                HttpResponse::BadRequest().json(response)
-            },
-            _ =>
-            {
-                let response = CommonResponse::<Product> {
-                    message: "Unknown error.".to_string(),
-                    data: None,
-                };
-                HttpResponse::BadRequest().json(response)
-            },
-        },
-    }
+    let product = collection
+        .create(content.into_inner(), collection_categories, collection_ingredients)
+        .await?;
+
+    let inserted_data = product.id;
+
+    DomainEvent::ProductChanged { change: Change::Created, product }.publish(&broadcaster);
+
+    Ok(HttpResponse::Ok().json(inserted_data))
 }
 
 pub async fn list(
     database_data: web::Data<Database>,
     query: web::Query<ProductListQuery>,
-) -> impl Responder
+) -> Result<HttpResponse, ApiError>
 {
     info!("List Products requested...");
 
@@ -69,141 +49,125 @@ pub async fn list(
 
     let collection = database_data.products().await;
 
-    let result = collection.list(offset, limit).await;
-
-    match result
-    {
-        Ok(products) => HttpResponse::Ok().json(products),
-        Err(error) =>
-        {
-            error!("Failed to get products. Error: {:?}", error);
-            HttpResponse::InternalServerError().finish()
-        },
-    }
+    let products = collection.list(&query.filter(), query.sort().as_ref(), offset, limit).await?;
+
+    Ok(HttpResponse::Ok().json(products))
 }
 
 pub async fn update(
     database_data: web::Data<Database>,
-    id: web::Path<String>,
-    content: web::Json<ProductUpdateRequest>,
-) -> impl Responder
+    broadcaster: web::Data<Mutex<Broadcaster>>,
+    id: EntityId,
+    content: ValidJson<ProductUpdateRequest>,
+) -> Result<HttpResponse, ApiError>
 {
     info!("Update Product requested...");
 
+    let internal_id = id.into_inner();
+
     let collection = database_data.products().await;
+    let collection_categories = database_data.categories().await;
+    let collection_ingredients = database_data.ingredients().await;
 
-    let update_result = collection
-        .update(id.into_inner(), content.into_inner())
-        .await;
-
-    match update_result
-    {
-        Ok(result) =>
-        {
-            let updated_data = result.modified_count;
-            let response = CommonResponse::<Product> {
-                message: format!("{} products updated.", updated_data),
-                data: None,
-            };
-            HttpResponse::Ok().json(response)
-        },
-        Err(error) =>
-        {
-            match error
-            {
-                ProductCollectionError::ProductNotFound =>
-                {
-                    let response = CommonResponse::<Product> {
-                        message: "Product not found.".to_string(),
-                        data: None,
-                    };
-                    HttpResponse::NotFound().json(response)
-                },
-                ProductCollectionError::ProductNotMofified =>
-                {
-                    let response = CommonResponse::<Product> {
-                        message: "Product not modified.".to_string(),
-                        data: None,
-                    };
-                    HttpResponse::BadRequest().json(response)
-                },
-                ProductCollectionError::CustomError(message) =>
-                {
-                    let response = CommonResponse::<Product> {
-                        message,
-                        data: None,
-                    };
-                    HttpResponse::BadRequest().json(response)
-                },
-                _ =>
-                {
-                    let response = CommonResponse::<Product> {
-                        message: "Unknown error.".to_string(),
-                        data: None,
-                    };
-                    HttpResponse::BadRequest().json(response)
-                },
-            }
-        },
-    }
+    let product = collection
+        .update(internal_id, content.into_inner(), collection_categories, collection_ingredients)
+        .await?;
+
+    DomainEvent::ProductChanged { change: Change::Updated, product: product.clone() }
+        .publish(&broadcaster);
+
+    let response = CommonResponse::<Product> {
+        message: "1 products updated.".to_string(),
+        data: Some(product),
+    };
+
+    Ok(HttpResponse::Ok().json(response))
 }
 
-pub async fn delete(database_data: web::Data<Database>, id: web::Path<String>) -> impl Responder
+pub async fn delete(
+    database_data: web::Data<Database>,
+    broadcaster: web::Data<Mutex<Broadcaster>>,
+    id: EntityId,
+) -> Result<HttpResponse, ApiError>
 {
     let collection = database_data.products().await;
 
-    let result = collection.delete(id.into_inner()).await;
-
-    match result
-    {
-        Ok(result) =>
-        {
-            if result.deleted_count == 0
-            {
-                let response = CommonResponse::<Product> {
-                    message: "Product not found.".to_string(),
-                    data: None,
-                };
-                HttpResponse::NotFound().json(response)
-            }
-            else
-            {
-                let response = CommonResponse::<Product> {
-                    message: format!("{} products deleted.", result.deleted_count),
-                    data: None,
-                };
-                HttpResponse::Ok().json(response)
-            }
-        },
-        Err(error) =>
-        {
-            let response = CommonResponse::<String> {
-                message: "Unknown error.".to_string(),
-                data: None,
-            };
-
-            error!("Failed to delete product. Error: {:?}", error);
-
-            HttpResponse::InternalServerError().json(response)
-        },
-    }
+    let product = collection.delete(id.into_inner()).await?;
+
+    DomainEvent::ProductChanged { change: Change::Deleted, product }.publish(&broadcaster);
+
+    let response = CommonResponse::<Product> {
+        message: "1 products deleted.".to_string(),
+        data: None,
+    };
+
+    Ok(HttpResponse::Ok().json(response))
 }
 
-pub async fn get(database_data: web::Data<Database>, id: web::Path<String>) -> impl Responder
+pub async fn get(
+    database_data: web::Data<Database>,
+    id: EntityId,
+) -> Result<HttpResponse, ApiError>
 {
     info!("Get Product by ID requested...");
 
     let collection = database_data.products().await;
 
-    let result = collection.get(id.into_inner()).await;
-
-    match result
-    {
-        Ok(product) => HttpResponse::Ok().json(product),
-        Err(error) =>
-        {
-            error!("Failed to get product. Error: {:?}", error);
-            HttpResponse::InternalServerError().finish()
-        },
-    }
+    let product = collection.get(id.into_inner()).await?;
+
+    Ok(HttpResponse::Ok().json(product))
 }
+
+
+pub async fn recipe(
+    database_data: web::Data<Database>,
+    id: EntityId,
+) -> Result<HttpResponse, ApiError>
+{
+    info!("Get Product recipe requested...");
+
+    let collection = database_data.products().await;
+    let collection_ingredients = database_data.ingredients().await;
+
+    let recipe = collection
+        .recipe(id.into_inner(), collection_ingredients)
+        .await?;
+
+    Ok(HttpResponse::Ok().json(recipe))
+}
+
+pub async fn list_unavailable(database_data: web::Data<Database>) -> Result<HttpResponse, ApiError>
+{
+    info!("List unavailable Products requested...");
+
+    let collection = database_data.products().await;
+
+    let products = collection.list_unavailable().await?;
+
+    Ok(HttpResponse::Ok().json(products))
+}
+
+pub async fn set_availability(
+    database_data: web::Data<Database>,
+    broadcaster: web::Data<Mutex<Broadcaster>>,
+    id: EntityId,
+    content: ValidJson<AvailabilityUpdateRequest>,
+) -> Result<HttpResponse, ApiError>
+{
+    info!("Set Product availability requested...");
+
+    let collection = database_data.products().await;
+
+    let product = collection
+        .set_availability(id.into_inner(), content.into_inner())
+        .await?;
+
+    DomainEvent::ProductAvailabilityChanged { product: product.clone() }.publish(&broadcaster);
+
+    let response = CommonResponse::<Product> {
+        message: "1 products updated.".to_string(),
+        data: Some(product),
+    };
+
+    Ok(HttpResponse::Ok().json(response))
+}
\ No newline at end of file
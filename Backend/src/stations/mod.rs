@@ -0,0 +1,16 @@
+pub mod model;
+pub mod routing;
+pub mod service;
+
+use actix_web::web;
+
+pub fn config(config: &mut web::ServiceConfig)
+{
+    config.service(
+        web::scope("/stations")
+        .service(
+            web::resource("/{station}/queue")
+            .route(web::get().to(service::queue))
+        )
+    );
+}
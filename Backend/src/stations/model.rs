@@ -0,0 +1,16 @@
+use chrono::{DateTime, Utc};
+use mongodb::bson::oid::ObjectId;
+use serde::{Deserialize, Serialize};
+
+use crate::orders::model::OrderItem;
+
+/// An open line item waiting at a station, with the order it belongs to
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct StationQueueItem
+{
+    pub order: Option<ObjectId>,
+    pub order_id: i32,
+    pub index: usize,
+    pub item: OrderItem,
+    pub ordered_at: DateTime<Utc>,
+}
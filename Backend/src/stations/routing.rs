@@ -0,0 +1,103 @@
+use std::{collections::HashMap, env};
+
+use crate::constants;
+use crate::products::model::{Product, ProductKind};
+
+/// Decides which kitchen station prepares a product
+#[derive(Debug, Clone)]
+pub struct StationRouting
+{
+    by_kind: HashMap<ProductKind, String>,
+}
+
+impl StationRouting
+{
+    pub fn new(by_kind: HashMap<ProductKind, String>) -> Self
+    {
+        StationRouting { by_kind }
+    }
+
+    /// Reads `STATION_ROUTES`, a comma separated list of `Kind:station` pairs
+    /// such as `Food:grill,Coctail:bar`
+    pub fn from_env() -> Self
+    {
+        let routes = env::var("STATION_ROUTES")
+            .unwrap_or_else(|_| constants::DEFAULT_STATION_ROUTES.to_string());
+
+        StationRouting::new(StationRouting::parse(&routes))
+    }
+
+    fn parse(routes: &str) -> HashMap<ProductKind, String>
+    {
+        let mut by_kind = HashMap::new();
+
+        for route in routes.split(',').map(str::trim).filter(|route| !route.is_empty())
+        {
+            let parsed = route.split_once(':').and_then(|(kind, station)| {
+                let kind = serde_json::Value::String(kind.trim().to_string());
+                let kind = serde_json::from_value(kind).ok()?;
+                Some((kind, station.trim().to_string()))
+            });
+
+            match parsed
+            {
+                Some((kind, station)) if !station.is_empty() =>
+                {
+                    by_kind.insert(kind, station);
+                },
+                _ => warn!("Ignoring invalid station route '{}'.", route),
+            }
+        }
+
+        by_kind
+    }
+
+    /// The station of a product: its own one when set, otherwise the one of its kind
+    pub fn station_for(&self, product: &Product) -> Option<String>
+    {
+        product
+            .station
+            .clone()
+            .or_else(|| self.by_kind.get(&product.kind).cloned())
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use serde_json::json;
+
+    use super::*;
+
+    fn product(kind: &str, station: Option<&str>) -> Product
+    {
+        let content = json!({ "name": "Dish", "price": "5.00", "kind": kind, "station": station });
+
+        serde_json::from_value(content).unwrap()
+    }
+
+    #[test]
+    fn routes_each_kind_to_its_station()
+    {
+        let routes = " Food:grill, Coctail : bar ,Dessert:pastry,ReadyMade:,Other";
+        let routing = StationRouting::new(StationRouting::parse(routes));
+
+        assert_eq!(routing.station_for(&product("Food", None)).as_deref(), Some("grill"));
+        assert_eq!(routing.station_for(&product("Coctail", None)).as_deref(), Some("bar"));
+
+        // kinds without a valid route go to no station
+        assert_eq!(routing.station_for(&product("ReadyMade", None)), None);
+        assert_eq!(routing.station_for(&product("Other", None)), None);
+    }
+
+    #[test]
+    fn prefers_the_station_of_the_product()
+    {
+        let routing = StationRouting::new(StationRouting::parse("Food:grill"));
+
+        let dessert = product("Food", Some("pastry"));
+
+        assert_eq!(routing.station_for(&dessert).as_deref(), Some("pastry"));
+        assert_eq!(routing.station_for(&product("Other", Some("pass"))).as_deref(), Some("pass"));
+    }
+}
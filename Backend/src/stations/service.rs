@@ -0,0 +1,68 @@
+use crate::database::Database;
+use crate::error::ApiError;
+use actix_web::Responder;
+use actix_web::{web, HttpResponse};
+
+pub async fn queue(
+    database_data: web::Data<Database>,
+    station: web::Path<String>,
+) -> Result<HttpResponse, ApiError>
+{
+    info!("Station Queue requested...");
+
+    let collection = database_data.orders().await;
+
+    let items = collection.station_queue(&station.into_inner()).await?;
+
+    Ok(HttpResponse::Ok().json(items))
+}
+
+#[cfg(test)]
+mod tests
+{
+    use actix_web::{test, web, App};
+    use serde_json::{json, Value};
+
+    use crate::database::tests::{memory, place, product};
+    use crate::orders::model::{OrderStatus, OrderUpdateRequest};
+
+    #[actix_web::test]
+    async fn queues_only_the_open_lines_of_a_station()
+    {
+        let database = memory().await;
+
+        // the default routes send food to the kitchen and cocktails to the bar
+        let burger = json!({ "name": "Burger", "price": "8.00", "kind": "Food" });
+        let mojito = json!({ "name": "Mojito", "price": "7.00", "kind": "Coctail" });
+        let spritz =
+            json!({ "name": "Spritz", "price": "6.00", "kind": "Coctail", "station": "terrace" });
+
+        let mut products = Vec::new();
+
+        for content in [burger, mojito, spritz]
+        {
+            products.push(product(&database, content).await.id.unwrap());
+        }
+
+        let open = place(&database, &products, 1).await.unwrap();
+        let cancelled = place(&database, &products[1..2], 2).await.unwrap();
+
+        let content = OrderUpdateRequest { status: OrderStatus::Cancelled };
+        database.orders().await.update(cancelled.id.unwrap(), content).await.unwrap();
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(database))
+                .configure(crate::config),
+        )
+        .await;
+
+        let request = test::TestRequest::get().uri("/v1/stations/bar/queue").to_request();
+        let queue: Value = test::call_and_read_body_json(&app, request).await;
+
+        assert_eq!(queue.as_array().unwrap().len(), 1);
+        assert_eq!(queue[0]["order_id"], open.order_id);
+        assert_eq!(queue[0]["index"], 1);
+        assert_eq!(queue[0]["item"]["name"], "Mojito");
+    }
+}
@@ -0,0 +1,313 @@
+use async_trait::async_trait;
+use mongodb::bson::{self, oid::ObjectId, Document};
+use std::marker::PhantomData;
+
+use super::{
+    apply_changes, matches, page_size, Counters, Entity, Filter, Sort, Storage, StorageError,
+    UpdateOutcome,
+};
+
+/// Opens (or creates) the embedded database at `path`
+pub fn open(path: &str) -> Result<sled::Db, StorageError>
+{
+    sled::open(path).map_err(StorageError::from)
+}
+
+/// Storage backed by a sled tree, with entities encoded as BSON
+///
+/// Keys are the raw `ObjectId` bytes, which start with a timestamp, so
+/// iterating a tree yields entities in insertion order.
+pub struct EmbeddedStorage<T: Entity>
+{
+    tree: sled::Tree,
+    marker: PhantomData<T>,
+}
+
+impl<T: Entity> EmbeddedStorage<T>
+{
+    pub fn new(db: &sled::Db, name: &str) -> Result<Self, StorageError>
+    {
+        Ok(EmbeddedStorage {
+            tree: db.open_tree(name)?,
+            marker: PhantomData,
+        })
+    }
+
+    fn encode(item: &T) -> Result<Vec<u8>, StorageError>
+    {
+        Ok(bson::to_vec(item)?)
+    }
+
+    fn decode(bytes: &[u8]) -> Result<T, StorageError>
+    {
+        Ok(bson::from_slice(bytes)?)
+    }
+
+    /// Reads a stored value as a document when it matches `filter`
+    fn read_matching(
+        value: sled::Result<sled::IVec>,
+        filter: &Filter,
+    ) -> Result<Option<Document>, StorageError>
+    {
+        let document = Document::from_reader(&value?[..])?;
+
+        if !filter.matches(&document)
+        {
+            return Ok(None);
+        }
+
+        Ok(Some(document))
+    }
+
+    /// Stored documents matching `filter`, in insertion order
+    fn matching<'a>(
+        &'a self,
+        filter: &'a Filter,
+    ) -> impl Iterator<Item = Result<Document, StorageError>> + 'a
+    {
+        self.tree
+            .iter()
+            .values()
+            .filter_map(move |value| Self::read_matching(value, filter).transpose())
+    }
+}
+
+impl From<sled::Error> for StorageError
+{
+    fn from(error: sled::Error) -> Self
+    {
+        StorageError::Backend(error.to_string())
+    }
+}
+
+#[async_trait]
+impl<T: Entity> Storage<T> for EmbeddedStorage<T>
+{
+    async fn insert(&self, mut item: T) -> Result<ObjectId, StorageError>
+    {
+        let id = item.id().unwrap_or_default();
+
+        item.set_id(id);
+
+        self.tree.insert(id.bytes(), Self::encode(&item)?)?;
+
+        Ok(id)
+    }
+
+    async fn get(&self, id: &ObjectId) -> Result<Option<T>, StorageError>
+    {
+        match self.tree.get(id.bytes())?
+        {
+            Some(value) => Ok(Some(Self::decode(&value)?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn find_one(&self, filter: &Filter) -> Result<Option<T>, StorageError>
+    {
+        match self.matching(filter).next().transpose()?
+        {
+            Some(document) => Ok(Some(bson::from_document(document)?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn find_sorted(
+        &self,
+        filter: &Filter,
+        sort: Option<&Sort>,
+        offset: u64,
+        limit: i64,
+    ) -> Result<Vec<T>, StorageError>
+    {
+        let sort = match sort
+        {
+            Some(sort) => sort,
+            None =>
+            {
+                return self
+                    .matching(filter)
+                    .skip(offset as usize)
+                    .take(page_size(limit))
+                    .map(|document| Ok(bson::from_document(document?)?))
+                    .collect();
+            },
+        };
+
+        // every match has to be read before the first of the page is known
+        let mut documents: Vec<Document> = self.matching(filter).collect::<Result<_, _>>()?;
+
+        sort.apply(&mut documents);
+
+        documents
+            .into_iter()
+            .skip(offset as usize)
+            .take(page_size(limit))
+            .map(|document| Ok(bson::from_document(document)?))
+            .collect()
+    }
+
+    async fn update_if(
+        &self,
+        id: &ObjectId,
+        expected: Document,
+        changes: Document,
+    ) -> Result<UpdateOutcome, StorageError>
+    {
+        loop
+        {
+            let current = match self.tree.get(id.bytes())?
+            {
+                Some(value) => value,
+                None => return Ok(UpdateOutcome::NOT_MATCHED),
+            };
+
+            // compare against the document as stored, which may predate the entity
+            let document = Document::from_reader(&current[..])?;
+
+            if !matches(&document, &expected)
+            {
+                return Ok(UpdateOutcome::NOT_MATCHED);
+            }
+
+            let document = match apply_changes(&document, changes.clone())
+            {
+                Some(document) => document,
+                None => return Ok(UpdateOutcome::UNCHANGED),
+            };
+
+            let updated: T = bson::from_document(document)?;
+
+            let swapped = self
+                .tree
+                .compare_and_swap(id.bytes(), Some(current), Some(Self::encode(&updated)?))?;
+
+            if swapped.is_ok()
+            {
+                return Ok(UpdateOutcome::MODIFIED);
+            }
+
+            // another writer got in between, so check again against its value
+        }
+    }
+
+    async fn delete(&self, id: &ObjectId) -> Result<bool, StorageError>
+    {
+        Ok(self.tree.remove(id.bytes())?.is_some())
+    }
+}
+
+/// Counters kept as big-endian `i64` values in a sled tree
+pub struct EmbeddedCounters
+{
+    tree: sled::Tree,
+}
+
+impl EmbeddedCounters
+{
+    pub fn new(db: &sled::Db, name: &str) -> Result<Self, StorageError>
+    {
+        Ok(EmbeddedCounters {
+            tree: db.open_tree(name)?,
+        })
+    }
+
+    fn decode(bytes: &[u8]) -> i64
+    {
+        bytes.try_into().map(i64::from_be_bytes).unwrap_or(0)
+    }
+}
+
+#[async_trait]
+impl Counters for EmbeddedCounters
+{
+    async fn next(&self, key: &str) -> Result<i64, StorageError>
+    {
+        let value = self.tree.update_and_fetch(key, |current| {
+            let next = current.map(Self::decode).unwrap_or(0) + 1;
+
+            Some(next.to_be_bytes().to_vec())
+        })?;
+
+        Ok(value.as_deref().map(Self::decode).unwrap_or(0))
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use mongodb::bson::doc;
+
+    use super::*;
+    use crate::categories::model::Category;
+
+    // a database in a directory of its own, removed when it is dropped
+    fn temporary() -> sled::Db
+    {
+        sled::Config::new().temporary(true).open().unwrap()
+    }
+
+    fn category(name: &str) -> Category
+    {
+        Category { id: None, name: name.to_string(), parent: None, position: 0 }
+    }
+
+    #[actix_web::test]
+    async fn updates_only_what_is_still_as_expected()
+    {
+        let storage = EmbeddedStorage::<Category>::new(&temporary(), "Categories").unwrap();
+
+        let id = storage.insert(category("Starters")).await.unwrap();
+
+        let moved = storage.update_if(&id, doc! { "position": 0 }, doc! { "position": 2 });
+
+        assert_eq!(moved.await.unwrap(), UpdateOutcome::MODIFIED);
+
+        // another writer already moved it, so the expected position is stale
+        let stale = storage.update_if(&id, doc! { "position": 0 }, doc! { "position": 5 });
+
+        assert!(!stale.await.unwrap().matched);
+        assert_eq!(storage.get(&id).await.unwrap().unwrap().position, 2);
+
+        let same = storage.update_if(&id, doc! { "position": 2 }, doc! { "position": 2 });
+
+        assert_eq!(same.await.unwrap(), UpdateOutcome::UNCHANGED);
+
+        let missing = storage.update(&ObjectId::new(), doc! { "position": 1 }).await.unwrap();
+
+        assert_eq!(missing, UpdateOutcome::NOT_MATCHED);
+    }
+
+    #[test]
+    fn counts_up_without_repeating_a_value()
+    {
+        let db = temporary();
+        let counters = EmbeddedCounters::new(&db, "Counters").unwrap();
+        let next = |key| futures::executor::block_on(counters.next(key)).unwrap();
+
+        let taken: Vec<Vec<i64>> = std::thread::scope(|scope| {
+            let threads: Vec<_> = (0..4)
+                .map(|_| scope.spawn(|| (0..25).map(|_| next("orders/all")).collect()))
+                .collect();
+
+            threads.into_iter().map(|thread| thread.join().unwrap()).collect()
+        });
+
+        // each caller sees the counter go up, and together they used every value once
+        for values in &taken
+        {
+            assert!(values.windows(2).all(|pair| pair[0] < pair[1]));
+        }
+
+        let mut all: Vec<i64> = taken.concat();
+        all.sort_unstable();
+
+        assert_eq!(all, (1..=100).collect::<Vec<i64>>());
+        assert_eq!(next("orders/2026-10-18"), 1);
+
+        // the count is kept in the database, not in the counters
+        let reopened = EmbeddedCounters::new(&db, "Counters").unwrap();
+
+        assert_eq!(futures::executor::block_on(reopened.next("orders/all")).unwrap(), 101);
+    }
+}
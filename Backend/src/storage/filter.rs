@@ -0,0 +1,287 @@
+use mongodb::bson::{doc, Bson, Document};
+use std::cmp::Ordering;
+
+/// A condition on stored documents, understood by every backend
+///
+/// Fields may be dotted paths (`products.station`); like MongoDB, a path that
+/// goes through an array matches when any of its elements does.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Filter
+{
+    All,
+    Eq(String, Bson),
+    In(String, Vec<Bson>),
+    /// None of the values is found at the field, which holds when it is missing
+    NotIn(String, Vec<Bson>),
+    /// The value at the field is the given one or more
+    Gte(String, Bson),
+    /// The value at the field is the given one or less
+    Lte(String, Bson),
+    /// The text at the field contains the given text, ignoring case
+    Contains(String, String),
+    And(Vec<Filter>),
+    /// At least one of the filters matches, which never holds when there are none
+    Or(Vec<Filter>),
+}
+
+impl Filter
+{
+    pub fn eq(field: &str, value: impl Into<Bson>) -> Self
+    {
+        Filter::Eq(field.to_string(), value.into())
+    }
+
+    pub fn one_of(field: &str, values: Vec<Bson>) -> Self
+    {
+        Filter::In(field.to_string(), values)
+    }
+
+    pub fn none_of(field: &str, values: Vec<Bson>) -> Self
+    {
+        Filter::NotIn(field.to_string(), values)
+    }
+
+    pub fn at_least(field: &str, value: impl Into<Bson>) -> Self
+    {
+        Filter::Gte(field.to_string(), value.into())
+    }
+
+    pub fn at_most(field: &str, value: impl Into<Bson>) -> Self
+    {
+        Filter::Lte(field.to_string(), value.into())
+    }
+
+    pub fn contains(field: &str, text: &str) -> Self
+    {
+        Filter::Contains(field.to_string(), text.to_string())
+    }
+
+    /// Combines two filters, either of which may match
+    pub fn or(self, other: Filter) -> Self
+    {
+        match (self, other)
+        {
+            (Filter::Or(mut filters), Filter::Or(others)) =>
+            {
+                filters.extend(others);
+                Filter::Or(filters)
+            },
+            (Filter::Or(mut filters), filter) | (filter, Filter::Or(mut filters)) =>
+            {
+                filters.push(filter);
+                Filter::Or(filters)
+            },
+            (filter, other) => Filter::Or(vec![filter, other]),
+        }
+    }
+
+    /// Combines two filters, both of which must match
+    pub fn and(self, other: Filter) -> Self
+    {
+        match (self, other)
+        {
+            (Filter::All, filter) | (filter, Filter::All) => filter,
+            (Filter::And(mut filters), Filter::And(others)) =>
+            {
+                filters.extend(others);
+                Filter::And(filters)
+            },
+            (Filter::And(mut filters), filter) | (filter, Filter::And(mut filters)) =>
+            {
+                filters.push(filter);
+                Filter::And(filters)
+            },
+            (filter, other) => Filter::And(vec![filter, other]),
+        }
+    }
+
+    /// The equivalent MongoDB query document
+    pub fn to_document(&self) -> Document
+    {
+        match self
+        {
+            Filter::All => Document::new(),
+            Filter::Eq(field, value) => doc! { field: value.clone() },
+            Filter::In(field, values) => doc! { field: { "$in": values.clone() } },
+            Filter::NotIn(field, values) => doc! { field: { "$nin": values.clone() } },
+            Filter::Gte(field, value) => doc! { field: { "$gte": value.clone() } },
+            Filter::Lte(field, value) => doc! { field: { "$lte": value.clone() } },
+            Filter::Contains(field, text) =>
+            {
+                doc! { field: { "$regex": escape(text), "$options": "i" } }
+            },
+            Filter::And(filters) if filters.is_empty() => Document::new(),
+            Filter::And(filters) =>
+            {
+                let filters: Vec<Document> = filters.iter().map(Filter::to_document).collect();
+                doc! { "$and": filters }
+            },
+            // MongoDB rejects an empty `$or`, so ask for something no document has
+            Filter::Or(filters) if filters.is_empty() => doc! { "_id": { "$in": [] } },
+            Filter::Or(filters) =>
+            {
+                let filters: Vec<Document> = filters.iter().map(Filter::to_document).collect();
+                doc! { "$or": filters }
+            },
+        }
+    }
+
+    /// Evaluates the filter against a document, for backends without a query engine
+    pub fn matches(&self, document: &Document) -> bool
+    {
+        match self
+        {
+            Filter::All => true,
+            Filter::Eq(field, value) => values_at(document, field)
+                .iter()
+                .any(|found| compare(found, value) == Some(Ordering::Equal)),
+            Filter::In(field, values) => any_of(document, field, values),
+            Filter::NotIn(field, values) => !any_of(document, field, values),
+            Filter::Gte(field, value) => values_at(document, field).iter().any(|found| {
+                matches!(compare(found, value), Some(Ordering::Greater | Ordering::Equal))
+            }),
+            Filter::Lte(field, value) => values_at(document, field).iter().any(|found| {
+                matches!(compare(found, value), Some(Ordering::Less | Ordering::Equal))
+            }),
+            Filter::Contains(field, text) =>
+            {
+                let text = text.to_lowercase();
+
+                values_at(document, field)
+                    .iter()
+                    .filter_map(|found| found.as_str())
+                    .any(|found| found.to_lowercase().contains(&text))
+            },
+            Filter::And(filters) => filters.iter().all(|filter| filter.matches(document)),
+            Filter::Or(filters) => filters.iter().any(|filter| filter.matches(document)),
+        }
+    }
+}
+
+/// Whether one of `values` is found at the field
+fn any_of(document: &Document, field: &str, values: &[Bson]) -> bool
+{
+    values_at(document, field).iter().any(|found| {
+        values
+            .iter()
+            .any(|value| compare(found, value) == Some(Ordering::Equal))
+    })
+}
+
+/// Escapes the characters with a meaning in a regular expression, to match text as is
+fn escape(text: &str) -> String
+{
+    let mut escaped = String::with_capacity(text.len());
+
+    for character in text.chars()
+    {
+        if "\\^$.|?*+()[]{}".contains(character)
+        {
+            escaped.push('\\');
+        }
+
+        escaped.push(character);
+    }
+
+    escaped
+}
+
+/// Values found at a dotted path, descending into arrays
+pub(super) fn values_at<'a>(document: &'a Document, path: &str) -> Vec<&'a Bson>
+{
+    let (field, rest) = match path.split_once('.')
+    {
+        Some((field, rest)) => (field, Some(rest)),
+        None => (path, None),
+    };
+
+    let value = match document.get(field)
+    {
+        Some(value) => value,
+        None => return Vec::new(),
+    };
+
+    match (value, rest)
+    {
+        (Bson::Document(inner), Some(rest)) => values_at(inner, rest),
+        (Bson::Array(elements), Some(rest)) => elements
+            .iter()
+            .filter_map(|element| element.as_document())
+            .flat_map(|inner| values_at(inner, rest))
+            .collect(),
+        (Bson::Array(elements), None) => std::iter::once(value).chain(elements.iter()).collect(),
+        (_, Some(_)) => Vec::new(),
+        (_, None) => vec![value],
+    }
+}
+
+/// Orders two values of comparable types, treating all numbers alike
+pub(super) fn compare(left: &Bson, right: &Bson) -> Option<Ordering>
+{
+    match (as_number(left), as_number(right))
+    {
+        (Some(left), Some(right)) => left.partial_cmp(&right),
+        _ => match (left, right)
+        {
+            (Bson::String(left), Bson::String(right)) => Some(left.cmp(right)),
+            (Bson::Boolean(left), Bson::Boolean(right)) => Some(left.cmp(right)),
+            (Bson::ObjectId(left), Bson::ObjectId(right)) => Some(left.cmp(right)),
+            (Bson::DateTime(left), Bson::DateTime(right)) => Some(left.cmp(right)),
+            (left, right) if left == right => Some(Ordering::Equal),
+            _ => None,
+        },
+    }
+}
+
+fn as_number(value: &Bson) -> Option<f64>
+{
+    match value
+    {
+        Bson::Int32(value) => Some(*value as f64),
+        Bson::Int64(value) => Some(*value as f64),
+        Bson::Double(value) => Some(*value),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+pub mod tests
+{
+    use super::*;
+
+    pub fn products() -> Vec<Document>
+    {
+        vec![
+            doc! { "name": "Burger", "price": { "amount": 950_i64 } },
+            doc! { "name": "apple pie", "price": { "amount": 420_i64 }, "remaining": 0_i64 },
+            doc! { "name": "Chips (large)" },
+            doc! { "name": "Cheeseburger", "price": { "amount": 1100_i64 } },
+        ]
+    }
+
+    pub fn names(documents: &[Document]) -> Vec<&str>
+    {
+        documents.iter().map(|document| document.get_str("name").unwrap()).collect()
+    }
+
+    #[test]
+    fn matches_ranges_text_and_alternatives()
+    {
+        let select = |filter: Filter| -> Vec<Document> {
+            products().into_iter().filter(|document| filter.matches(document)).collect()
+        };
+
+        let priced =
+            Filter::at_least("price.amount", 420).and(Filter::at_most("price.amount", 950));
+
+        assert_eq!(names(&select(priced)), ["Burger", "apple pie"]);
+        assert_eq!(names(&select(Filter::contains("name", "BURGER"))), ["Burger", "Cheeseburger"]);
+        assert_eq!(names(&select(Filter::contains("name", "(l"))), ["Chips (large)"]);
+        assert_eq!(escape("(l.*"), "\\(l\\.\\*");
+
+        let either = Filter::eq("remaining", 0).or(Filter::eq("name", "Burger"));
+
+        assert_eq!(names(&select(either)), ["Burger", "apple pie"]);
+        assert!(select(Filter::Or(vec![])).is_empty());
+    }
+}
\ No newline at end of file
@@ -0,0 +1,270 @@
+use async_trait::async_trait;
+use mongodb::bson::{self, oid::ObjectId, Document};
+use std::{
+    collections::{BTreeMap, HashMap},
+    marker::PhantomData,
+    sync::{Arc, Mutex, RwLock},
+};
+
+use super::{
+    apply_changes, matches, page_size, to_document, Counters, Entity, Filter, Sort, Storage,
+    StorageError, UpdateOutcome,
+};
+
+type Table = Arc<RwLock<BTreeMap<ObjectId, Document>>>;
+
+/// A set of named in-memory tables, shared by every clone
+///
+/// Nothing is persisted; dropping the last clone discards all data.
+#[derive(Clone, Default)]
+pub struct MemoryDatabase
+{
+    tables: Arc<Mutex<HashMap<String, Table>>>,
+    counters: Arc<Mutex<HashMap<String, i64>>>,
+}
+
+impl MemoryDatabase
+{
+    fn table(&self, name: &str) -> Table
+    {
+        self.tables
+            .lock()
+            .unwrap()
+            .entry(name.to_string())
+            .or_default()
+            .clone()
+    }
+}
+
+/// Storage backed by an in-memory table, with entities kept as BSON documents
+pub struct MemoryStorage<T: Entity>
+{
+    table: Table,
+    marker: PhantomData<T>,
+}
+
+impl<T: Entity> MemoryStorage<T>
+{
+    pub fn new(database: &MemoryDatabase, name: &str) -> Self
+    {
+        MemoryStorage {
+            table: database.table(name),
+            marker: PhantomData,
+        }
+    }
+}
+
+#[async_trait]
+impl<T: Entity> Storage<T> for MemoryStorage<T>
+{
+    async fn insert(&self, mut item: T) -> Result<ObjectId, StorageError>
+    {
+        let id = item.id().unwrap_or_default();
+
+        item.set_id(id);
+
+        self.table.write().unwrap().insert(id, to_document(&item)?);
+
+        Ok(id)
+    }
+
+    async fn get(&self, id: &ObjectId) -> Result<Option<T>, StorageError>
+    {
+        match self.table.read().unwrap().get(id)
+        {
+            Some(document) => Ok(Some(bson::from_document(document.clone())?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn find_one(&self, filter: &Filter) -> Result<Option<T>, StorageError>
+    {
+        let table = self.table.read().unwrap();
+
+        match table.values().find(|document| filter.matches(document))
+        {
+            Some(document) => Ok(Some(bson::from_document(document.clone())?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn find_sorted(
+        &self,
+        filter: &Filter,
+        sort: Option<&Sort>,
+        offset: u64,
+        limit: i64,
+    ) -> Result<Vec<T>, StorageError>
+    {
+        let table = self.table.read().unwrap();
+
+        let mut documents: Vec<&Document> =
+            table.values().filter(|document| filter.matches(document)).collect();
+
+        if let Some(sort) = sort
+        {
+            documents.sort_by(|left, right| sort.compare(left, right));
+        }
+
+        documents
+            .into_iter()
+            .skip(offset as usize)
+            .take(page_size(limit))
+            .map(|document| Ok(bson::from_document(document.clone())?))
+            .collect()
+    }
+
+    async fn update_if(
+        &self,
+        id: &ObjectId,
+        expected: Document,
+        changes: Document,
+    ) -> Result<UpdateOutcome, StorageError>
+    {
+        let mut table = self.table.write().unwrap();
+
+        let current = match table.get(id)
+        {
+            Some(document) if matches(document, &expected) => document,
+            _ => return Ok(UpdateOutcome::NOT_MATCHED),
+        };
+
+        let document = match apply_changes(current, changes)
+        {
+            Some(document) => document,
+            None => return Ok(UpdateOutcome::UNCHANGED),
+        };
+
+        // round-trip through the entity so changes that do not fit it are rejected
+        let updated: T = bson::from_document(document)?;
+
+        table.insert(*id, to_document(&updated)?);
+
+        Ok(UpdateOutcome::MODIFIED)
+    }
+
+    async fn delete(&self, id: &ObjectId) -> Result<bool, StorageError>
+    {
+        Ok(self.table.write().unwrap().remove(id).is_some())
+    }
+}
+
+/// Counters kept in process memory
+pub struct MemoryCounters
+{
+    database: MemoryDatabase,
+    name: String,
+}
+
+impl MemoryCounters
+{
+    pub fn new(database: &MemoryDatabase, name: &str) -> Self
+    {
+        MemoryCounters {
+            database: database.clone(),
+            name: name.to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl Counters for MemoryCounters
+{
+    async fn next(&self, key: &str) -> Result<i64, StorageError>
+    {
+        let mut counters = self.database.counters.lock().unwrap();
+
+        let value = counters.entry(format!("{}/{}", self.name, key)).or_insert(0);
+
+        *value += 1;
+
+        Ok(*value)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use mongodb::bson::doc;
+
+    use super::*;
+    use crate::categories::model::Category;
+    use crate::storage::SortOrder;
+
+    async fn categories(names: &[&str]) -> (MemoryStorage<Category>, Vec<ObjectId>)
+    {
+        let storage = MemoryStorage::new(&MemoryDatabase::default(), "Categories");
+        let mut ids = Vec::new();
+
+        for (position, name) in names.iter().enumerate()
+        {
+            let category = Category {
+                id: None,
+                name: name.to_string(),
+                parent: None,
+                position: position as i32,
+            };
+
+            ids.push(storage.insert(category).await.unwrap());
+        }
+
+        (storage, ids)
+    }
+
+    fn names(categories: &[Category]) -> Vec<&str>
+    {
+        categories.iter().map(|category| category.name.as_str()).collect()
+    }
+
+    #[actix_web::test]
+    async fn sorts_before_taking_a_page()
+    {
+        let (storage, _) = categories(&["Mains", "desserts", "Starters", "Sides", "Drinks"]).await;
+
+        let sort = Sort::new("name", SortOrder::Asc).ignoring_case();
+        let page = |offset, limit| storage.find_sorted(&Filter::All, Some(&sort), offset, limit);
+
+        assert_eq!(names(&page(1, 2).await.unwrap()), ["Drinks", "Mains"]);
+        assert_eq!(names(&page(3, 0).await.unwrap()), ["Sides", "Starters"]);
+        assert!(page(5, 2).await.unwrap().is_empty());
+
+        // without a sort, pages follow insertion order
+        let page = storage.find(&Filter::at_least("position", 1), 1, 2).await.unwrap();
+
+        assert_eq!(names(&page), ["Starters", "Sides"]);
+    }
+
+    #[actix_web::test]
+    async fn updates_only_what_is_still_as_expected()
+    {
+        let (storage, ids) = categories(&["Starters"]).await;
+        let id = ids[0];
+
+        let moved = storage.update_if(&id, doc! { "position": 0 }, doc! { "position": 2 });
+
+        assert_eq!(moved.await.unwrap(), UpdateOutcome::MODIFIED);
+
+        let stale = storage.update_if(&id, doc! { "position": 0 }, doc! { "position": 5 });
+
+        assert_eq!(stale.await.unwrap(), UpdateOutcome::NOT_MATCHED);
+        assert_eq!(storage.get(&id).await.unwrap().unwrap().position, 2);
+
+        // changes that do not fit the entity are refused and leave it as it was
+        let invalid = storage.update(&id, doc! { "position": "first" }).await;
+
+        assert!(matches!(invalid, Err(StorageError::Serialization(_))));
+        assert_eq!(storage.get(&id).await.unwrap().unwrap().position, 2);
+    }
+
+    #[actix_web::test]
+    async fn deletes_only_what_exists()
+    {
+        let (storage, ids) = categories(&["Starters", "Mains"]).await;
+
+        assert!(storage.delete(&ids[0]).await.unwrap());
+        assert!(!storage.delete(&ids[0]).await.unwrap());
+        assert!(!storage.delete(&ObjectId::new()).await.unwrap());
+
+        assert_eq!(names(&storage.list(0, 0).await.unwrap()), ["Mains"]);
+    }
+}
@@ -0,0 +1,319 @@
+pub mod embedded;
+pub mod filter;
+pub mod memory;
+pub mod mongo;
+pub mod sort;
+
+pub use filter::Filter;
+pub use sort::{Sort, SortOrder};
+
+use async_trait::async_trait;
+use mongodb::bson::{self, oid::ObjectId, Document};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{env, fmt, sync::Arc};
+
+use crate::constants;
+
+/// A document that can be persisted by any storage backend
+pub trait Entity: Serialize + DeserializeOwned + Clone + Send + Sync + Unpin + 'static
+{
+    fn id(&self) -> Option<ObjectId>;
+
+    fn set_id(&mut self, id: ObjectId);
+}
+
+#[derive(Debug)]
+pub enum StorageError
+{
+    InvalidConfiguration(String),
+    Backend(String),
+    Serialization(String),
+}
+
+impl fmt::Display for StorageError
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        match self
+        {
+            StorageError::InvalidConfiguration(message) =>
+            {
+                write!(f, "Invalid storage configuration: {}", message)
+            },
+            StorageError::Backend(message) => write!(f, "Storage backend error: {}", message),
+            StorageError::Serialization(message) =>
+            {
+                write!(f, "Storage serialization error: {}", message)
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UpdateOutcome
+{
+    pub matched: bool,
+    pub modified: bool,
+}
+
+impl UpdateOutcome
+{
+    pub const NOT_MATCHED: UpdateOutcome = UpdateOutcome {
+        matched: false,
+        modified: false,
+    };
+
+    pub const UNCHANGED: UpdateOutcome = UpdateOutcome {
+        matched: true,
+        modified: false,
+    };
+
+    pub const MODIFIED: UpdateOutcome = UpdateOutcome {
+        matched: true,
+        modified: true,
+    };
+}
+
+impl From<bson::ser::Error> for StorageError
+{
+    fn from(error: bson::ser::Error) -> Self
+    {
+        StorageError::Serialization(error.to_string())
+    }
+}
+
+impl From<bson::de::Error> for StorageError
+{
+    fn from(error: bson::de::Error) -> Self
+    {
+        StorageError::Serialization(error.to_string())
+    }
+}
+
+/// Storage operations the collections are written against
+///
+/// Every backend stores entities keyed by their `ObjectId` and returns them in
+/// insertion order unless asked to sort them.
+#[async_trait]
+pub trait Storage<T: Entity>: Send + Sync
+{
+    /// Inserts a new entity, assigning an id when it has none
+    async fn insert(&self, item: T) -> Result<ObjectId, StorageError>;
+
+    /// Gets a single entity by id
+    async fn get(&self, id: &ObjectId) -> Result<Option<T>, StorageError>;
+
+    /// Gets the first entity matching `filter`
+    async fn find_one(&self, filter: &Filter) -> Result<Option<T>, StorageError>;
+
+    /// Lists entities matching `filter`, a `limit` of zero meaning no limit
+    async fn find(&self, filter: &Filter, offset: u64, limit: i64) -> Result<Vec<T>, StorageError>
+    {
+        self.find_sorted(filter, None, offset, limit).await
+    }
+
+    /// Like `find`, sorting the matching entities before taking the page when `sort`
+    /// is given
+    async fn find_sorted(
+        &self,
+        filter: &Filter,
+        sort: Option<&Sort>,
+        offset: u64,
+        limit: i64,
+    ) -> Result<Vec<T>, StorageError>;
+
+    /// Lists all entities, a `limit` of zero meaning no limit
+    async fn list(&self, offset: u64, limit: i64) -> Result<Vec<T>, StorageError>
+    {
+        self.find(&Filter::All, offset, limit).await
+    }
+
+    /// Sets the given top-level fields on the entity with the given id
+    async fn update(&self, id: &ObjectId, changes: Document) -> Result<UpdateOutcome, StorageError>
+    {
+        self.update_if(id, Document::new(), changes).await
+    }
+
+    /// Like `update`, but only when the entity currently matches `expected`,
+    /// checked atomically with the write
+    async fn update_if(
+        &self,
+        id: &ObjectId,
+        expected: Document,
+        changes: Document,
+    ) -> Result<UpdateOutcome, StorageError>;
+
+    /// Deletes an entity by id, returning whether it existed
+    async fn delete(&self, id: &ObjectId) -> Result<bool, StorageError>;
+
+    /// Indexes the given fields, in that order, so filtering and sorting on them
+    /// need not look at every entity; backends that always scan ignore it
+    async fn index(&self, fields: &[&str]) -> Result<(), StorageError>
+    {
+        Ok(())
+    }
+}
+
+/// Named counters that are incremented atomically
+#[async_trait]
+pub trait Counters: Send + Sync
+{
+    /// Increments the counter named `key`, starting from zero, and returns its new value
+    async fn next(&self, key: &str) -> Result<i64, StorageError>;
+}
+
+/// The storage backend selected at startup
+#[derive(Clone)]
+pub enum Backend
+{
+    Mongo(mongodb::Database),
+    Embedded(sled::Db),
+    Memory(memory::MemoryDatabase),
+}
+
+impl Backend
+{
+    /// Opens the backend named by the `STORAGE_BACKEND` environment variable
+    ///
+    /// * `mongo` (default) - connects to `MONGO_URI`
+    /// * `sled` - opens an embedded database at `SLED_PATH`
+    /// * `memory` - keeps everything in process memory, for tests and demos
+    ///
+    pub async fn from_env() -> Result<Self, StorageError>
+    {
+        let kind = env::var("STORAGE_BACKEND")
+            .unwrap_or_else(|_| constants::DEFAULT_STORAGE_BACKEND.to_string());
+
+        match kind.to_lowercase().as_str()
+        {
+            "mongo" | "mongodb" =>
+            {
+                let uri = env::var("MONGO_URI").map_err(|_| {
+                    StorageError::InvalidConfiguration("MONGO_URI is not set.".to_string())
+                })?;
+
+                mongo::open(&uri).await.map(Backend::Mongo)
+            },
+            "sled" | "embedded" =>
+            {
+                let path = env::var("SLED_PATH")
+                    .unwrap_or_else(|_| constants::DEFAULT_SLED_PATH.to_string());
+
+                embedded::open(&path).map(Backend::Embedded)
+            },
+            "memory" => Ok(Backend::Memory(memory::MemoryDatabase::default())),
+            other => Err(StorageError::InvalidConfiguration(format!(
+                "Unknown storage backend '{}'.",
+                other
+            ))),
+        }
+    }
+
+    /// Returns the storage for the named collection
+    pub fn collection<T: Entity>(&self, name: &str) -> Result<Arc<dyn Storage<T>>, StorageError>
+    {
+        match self
+        {
+            Backend::Mongo(database) => Ok(Arc::new(mongo::MongoStorage::new(database, name))),
+            Backend::Embedded(db) => Ok(Arc::new(embedded::EmbeddedStorage::new(db, name)?)),
+            Backend::Memory(database) => Ok(Arc::new(memory::MemoryStorage::new(database, name))),
+        }
+    }
+
+    /// Returns the counters kept in the named collection
+    pub fn counters(&self, name: &str) -> Result<Arc<dyn Counters>, StorageError>
+    {
+        match self
+        {
+            Backend::Mongo(database) => Ok(Arc::new(mongo::MongoCounters::new(database, name))),
+            Backend::Embedded(db) => Ok(Arc::new(embedded::EmbeddedCounters::new(db, name)?)),
+            Backend::Memory(database) => Ok(Arc::new(memory::MemoryCounters::new(database, name))),
+        }
+    }
+}
+
+/// Applies top-level field changes to a document, returning `None` when nothing changed
+fn apply_changes(original: &Document, changes: Document) -> Option<Document>
+{
+    let mut document = original.clone();
+
+    for (field, value) in changes
+    {
+        document.insert(field, value);
+    }
+
+    if document == *original
+    {
+        None
+    }
+    else
+    {
+        Some(document)
+    }
+}
+
+/// Whether every field of `filter` has the same value in `document`
+fn matches(document: &Document, filter: &Document) -> bool
+{
+    filter.iter().all(|(field, value)| document.get(field) == Some(value))
+}
+
+/// Number of entities a `limit` allows, following MongoDB where zero means no limit
+fn page_size(limit: i64) -> usize
+{
+    match limit
+    {
+        0 => usize::MAX,
+        limit => limit.unsigned_abs() as usize,
+    }
+}
+
+/// Encodes a value the way it is stored, rather than the way it is sent to clients
+///
+/// Types such as `Money` serialize to a compact string for humans and to exact
+/// fields in the database; changes passed to `update` must use the stored form.
+pub fn to_bson<T: Serialize + ?Sized>(value: &T) -> Result<bson::Bson, StorageError>
+{
+    let options = bson::SerializerOptions::builder().human_readable(false).build();
+
+    Ok(bson::to_bson_with_options(value, options)?)
+}
+
+/// Like `to_bson`, for values that encode to a document
+pub fn to_document<T: Serialize + ?Sized>(value: &T) -> Result<Document, StorageError>
+{
+    let options = bson::SerializerOptions::builder().human_readable(false).build();
+
+    Ok(bson::to_document_with_options(value, options)?)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[actix_web::test]
+    async fn opens_the_backend_named_in_the_environment()
+    {
+        // the only test reading these variables, so setting them cannot race another
+        let open = |kind: &str| {
+            env::set_var("STORAGE_BACKEND", kind);
+            Backend::from_env()
+        };
+
+        assert!(matches!(open("memory").await, Ok(Backend::Memory(_))));
+
+        let path = env::temp_dir().join(format!("KitchenManager-{}", ObjectId::new()));
+        env::set_var("SLED_PATH", &path);
+
+        assert!(matches!(open("Embedded").await, Ok(Backend::Embedded(_))));
+        assert!(path.exists());
+
+        env::remove_var("MONGO_URI");
+
+        assert!(matches!(open("mongo").await, Err(StorageError::InvalidConfiguration(_))));
+        assert!(matches!(open("postgres").await, Err(StorageError::InvalidConfiguration(_))));
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+}
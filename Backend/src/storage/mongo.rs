@@ -0,0 +1,175 @@
+use async_trait::async_trait;
+use futures::StreamExt;
+use mongodb::{
+    bson::{doc, oid::ObjectId, Document},
+    options::{FindOneAndUpdateOptions, FindOptions, ReturnDocument},
+    Client, Collection, IndexModel,
+};
+
+use super::{Counters, Entity, Filter, Sort, Storage, StorageError, UpdateOutcome};
+
+const DATABASE_NAME: &str = "KitchenManager";
+
+/// Connects to the MongoDB server at `uri`
+pub async fn open(uri: &str) -> Result<mongodb::Database, StorageError>
+{
+    let client = Client::with_uri_str(uri)
+        .await
+        .map_err(|error| StorageError::Backend(error.to_string()))?;
+
+    Ok(client.database(DATABASE_NAME))
+}
+
+/// Storage backed by a MongoDB collection
+pub struct MongoStorage<T: Entity>
+{
+    collection: Collection<T>,
+}
+
+impl<T: Entity> MongoStorage<T>
+{
+    pub fn new(database: &mongodb::Database, name: &str) -> Self
+    {
+        MongoStorage {
+            collection: database.collection(name),
+        }
+    }
+}
+
+impl From<mongodb::error::Error> for StorageError
+{
+    fn from(error: mongodb::error::Error) -> Self
+    {
+        StorageError::Backend(error.to_string())
+    }
+}
+
+#[async_trait]
+impl<T: Entity> Storage<T> for MongoStorage<T>
+{
+    async fn insert(&self, item: T) -> Result<ObjectId, StorageError>
+    {
+        let result = self.collection.insert_one(item, None).await?;
+
+        result
+            .inserted_id
+            .as_object_id()
+            .ok_or_else(|| StorageError::Backend("Inserted id is not an ObjectId.".to_string()))
+    }
+
+    async fn get(&self, id: &ObjectId) -> Result<Option<T>, StorageError>
+    {
+        Ok(self.collection.find_one(doc! { "_id": id }, None).await?)
+    }
+
+    async fn find_one(&self, filter: &Filter) -> Result<Option<T>, StorageError>
+    {
+        Ok(self.collection.find_one(filter.to_document(), None).await?)
+    }
+
+    async fn find_sorted(
+        &self,
+        filter: &Filter,
+        sort: Option<&Sort>,
+        offset: u64,
+        limit: i64,
+    ) -> Result<Vec<T>, StorageError>
+    {
+        let find_options = FindOptions::builder()
+            .sort(sort.map(Sort::to_document))
+            .collation(sort.and_then(Sort::collation))
+            .skip(offset)
+            .limit(limit)
+            .build();
+
+        let mut cursor = self.collection.find(filter.to_document(), find_options).await?;
+
+        let mut items: Vec<T> = Vec::new();
+
+        while let Some(result) = cursor.next().await
+        {
+            items.push(result?);
+        }
+
+        Ok(items)
+    }
+
+    async fn update_if(
+        &self,
+        id: &ObjectId,
+        expected: Document,
+        changes: Document,
+    ) -> Result<UpdateOutcome, StorageError>
+    {
+        let mut filter = expected;
+        filter.insert("_id", id);
+
+        let update = doc! { "$set": changes };
+
+        let result = self.collection.update_one(filter, update, None).await?;
+
+        Ok(UpdateOutcome {
+            matched: result.matched_count > 0,
+            modified: result.modified_count > 0,
+        })
+    }
+
+    async fn delete(&self, id: &ObjectId) -> Result<bool, StorageError>
+    {
+        let result = self.collection.delete_one(doc! { "_id": id }, None).await?;
+
+        Ok(result.deleted_count > 0)
+    }
+
+    async fn index(&self, fields: &[&str]) -> Result<(), StorageError>
+    {
+        let mut keys = Document::new();
+
+        for field in fields
+        {
+            keys.insert(*field, 1);
+        }
+
+        // creating an index that already exists does nothing
+        self.collection.create_index(IndexModel::builder().keys(keys).build(), None).await?;
+
+        Ok(())
+    }
+}
+
+/// Counters kept as `{ _id: key, value }` documents in a MongoDB collection
+pub struct MongoCounters
+{
+    collection: Collection<Document>,
+}
+
+impl MongoCounters
+{
+    pub fn new(database: &mongodb::Database, name: &str) -> Self
+    {
+        MongoCounters {
+            collection: database.collection(name),
+        }
+    }
+}
+
+#[async_trait]
+impl Counters for MongoCounters
+{
+    async fn next(&self, key: &str) -> Result<i64, StorageError>
+    {
+        let options = FindOneAndUpdateOptions::builder()
+            .upsert(true)
+            .return_document(ReturnDocument::After)
+            .build();
+
+        let counter = self
+            .collection
+            .find_one_and_update(doc! { "_id": key }, doc! { "$inc": { "value": 1_i64 } }, options)
+            .await?;
+
+        counter
+            .and_then(|counter| counter.get_i64("value").ok())
+            .ok_or_else(|| StorageError::Backend(format!("Counter '{}' was not incremented.", key)))
+    }
+}
@@ -0,0 +1,169 @@
+use mongodb::bson::{doc, Bson, Document};
+use mongodb::options::{Collation, CollationStrength};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+
+use super::filter::{compare, values_at};
+
+/// Whether a listing runs from the lowest value up or from the highest down
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder
+{
+    Asc,
+    Desc,
+}
+
+/// The order entities are listed in, by the value at one (possibly dotted) field
+///
+/// Entities with equal values keep their insertion order. Like MongoDB, a missing
+/// field sorts before any value, and values of different types are ordered by type.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sort
+{
+    pub field: String,
+    pub order: SortOrder,
+    /// Whether text is compared regardless of case, e.g. for names
+    pub ignore_case: bool,
+}
+
+impl Sort
+{
+    pub fn new(field: &str, order: SortOrder) -> Self
+    {
+        Sort {
+            field: field.to_string(),
+            order,
+            ignore_case: false,
+        }
+    }
+
+    /// The same sort, comparing text regardless of case
+    pub fn ignoring_case(self) -> Self
+    {
+        Sort { ignore_case: true, ..self }
+    }
+
+    /// The equivalent MongoDB sort document, falling back to insertion order
+    pub fn to_document(&self) -> Document
+    {
+        let direction = match self.order
+        {
+            SortOrder::Asc => 1,
+            SortOrder::Desc => -1,
+        };
+
+        let mut document = doc! { self.field.as_str(): direction };
+
+        if self.field != "_id"
+        {
+            document.insert("_id", 1);
+        }
+
+        document
+    }
+
+    /// The MongoDB collation comparing text regardless of case as `compare` does, if
+    /// the default binary comparison would not; accents still count in both
+    pub fn collation(&self) -> Option<Collation>
+    {
+        match self.ignore_case
+        {
+            true => Some(
+                Collation::builder()
+                    .locale("en")
+                    .strength(CollationStrength::Secondary)
+                    .build(),
+            ),
+            false => None,
+        }
+    }
+
+    /// Orders two documents, for backends without a query engine
+    pub fn compare(&self, left: &Document, right: &Document) -> Ordering
+    {
+        let left = values_at(left, &self.field).first().copied();
+        let right = values_at(right, &self.field).first().copied();
+
+        let ordering = match (left, right)
+        {
+            (Some(Bson::String(left)), Some(Bson::String(right))) if self.ignore_case =>
+            {
+                left.to_lowercase().cmp(&right.to_lowercase())
+            },
+            (Some(left), Some(right)) => compare(left, right)
+                .unwrap_or_else(|| rank(Some(left)).cmp(&rank(Some(right)))),
+            (left, right) => rank(left).cmp(&rank(right)),
+        };
+
+        match self.order
+        {
+            SortOrder::Asc => ordering,
+            SortOrder::Desc => ordering.reverse(),
+        }
+    }
+
+    /// Sorts documents in place, keeping equal ones in the order they came in
+    pub fn apply(&self, documents: &mut [Document])
+    {
+        documents.sort_by(|left, right| self.compare(left, right));
+    }
+}
+
+/// Where values of each type go relative to other types, following MongoDB
+fn rank(value: Option<&Bson>) -> u8
+{
+    match value
+    {
+        None | Some(Bson::Null) => 0,
+        Some(Bson::Int32(_) | Bson::Int64(_) | Bson::Double(_)) => 1,
+        Some(Bson::String(_)) => 2,
+        Some(Bson::Document(_)) => 3,
+        Some(Bson::Array(_)) => 4,
+        Some(Bson::ObjectId(_)) => 5,
+        Some(Bson::Boolean(_)) => 6,
+        Some(Bson::DateTime(_)) => 7,
+        Some(_) => 8,
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::storage::filter::tests::{names, products};
+
+    #[test]
+    fn sorts_missing_fields_first_and_keeps_ties_in_order()
+    {
+        let mut documents = products();
+
+        Sort::new("price.amount", SortOrder::Asc).apply(&mut documents);
+
+        assert_eq!(
+            names(&documents),
+            ["Chips (large)", "apple pie", "Burger", "Cheeseburger"]
+        );
+
+        Sort::new("name", SortOrder::Desc).ignoring_case().apply(&mut documents);
+
+        assert_eq!(names(&documents), ["Chips (large)", "Cheeseburger", "Burger", "apple pie"]);
+
+        Sort::new("kind", SortOrder::Asc).apply(&mut documents);
+
+        assert_eq!(names(&documents), ["Chips (large)", "Cheeseburger", "Burger", "apple pie"]);
+
+        // exact text comparison still puts capitals first
+        Sort::new("name", SortOrder::Asc).apply(&mut documents);
+
+        assert_eq!(names(&documents), ["Burger", "Cheeseburger", "Chips (large)", "apple pie"]);
+
+        documents.push(doc! { "name": "burger" });
+        Sort::new("name", SortOrder::Asc).ignoring_case().apply(&mut documents);
+
+        assert_eq!(
+            names(&documents),
+            ["apple pie", "Burger", "burger", "Cheeseburger", "Chips (large)"]
+        );
+    }
+}
\ No newline at end of file
@@ -0,0 +1,224 @@
+use mongodb::bson::oid::ObjectId;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use validator::{ValidationError, ValidationErrors, ValidationErrorsKind};
+
+use crate::money::Money;
+use crate::products::model::ModifierGroup;
+
+/// A problem with one field of a request, reported back to the client
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct FieldError
+{
+    pub code: String,
+    pub message: String,
+}
+
+/// Field errors keyed by the path of the field, e.g. `products[0].quantity`
+pub type FieldErrors = BTreeMap<String, Vec<FieldError>>;
+
+/// Rejects strings made up of whitespace only
+pub fn not_blank(value: &str) -> Result<(), ValidationError>
+{
+    if value.trim().is_empty()
+    {
+        return Err(error("blank", "must not be blank"));
+    }
+
+    Ok(())
+}
+
+/// Rejects negative prices
+pub fn price(value: &Money) -> Result<(), ValidationError>
+{
+    if value.is_negative()
+    {
+        return Err(error("price", "must be an amount of zero or more"));
+    }
+
+    Ok(())
+}
+
+/// Rejects quantities of zero or less, and ones that are not numbers
+pub fn positive(value: f64) -> Result<(), ValidationError>
+{
+    if !(value > 0.0 && value.is_finite())
+    {
+        return Err(error("positive", "must be more than zero"));
+    }
+
+    Ok(())
+}
+
+/// Rejects negative quantities, and ones that are not numbers
+pub fn not_negative(value: f64) -> Result<(), ValidationError>
+{
+    if !(value >= 0.0 && value.is_finite())
+    {
+        return Err(error("not_negative", "must be zero or more"));
+    }
+
+    Ok(())
+}
+
+pub fn object_id(value: &str) -> Result<(), ValidationError>
+{
+    if ObjectId::parse_str(value).is_err()
+    {
+        return Err(error("object_id", "must be a valid id"));
+    }
+
+    Ok(())
+}
+
+/// Checks the limits of a modifier group and that its options can be told apart
+pub fn modifier_group(group: &ModifierGroup) -> Result<(), ValidationError>
+{
+    if let Some(max) = group.max
+    {
+        if max == 0 || max < group.min_choices()
+        {
+            return Err(error("modifier_limits", "max must be at least 1 and at least min"));
+        }
+    }
+
+    let mut names: Vec<&str> = group.options.iter().map(|option| option.name.as_str()).collect();
+
+    names.sort_unstable();
+
+    if names.windows(2).any(|pair| pair[0] == pair[1])
+    {
+        return Err(error("modifier_options", "options must have different names"));
+    }
+
+    Ok(())
+}
+
+fn error(code: &'static str, message: &'static str) -> ValidationError
+{
+    let mut error = ValidationError::new(code);
+    error.message = Some(message.into());
+    error
+}
+
+/// Flattens nested validation errors into one list per field path
+pub fn field_errors(errors: &ValidationErrors) -> FieldErrors
+{
+    let mut fields = FieldErrors::new();
+
+    collect(errors, "", &mut fields);
+
+    fields
+}
+
+fn collect(errors: &ValidationErrors, prefix: &str, fields: &mut FieldErrors)
+{
+    for (field, kind) in errors.errors()
+    {
+        // errors of a whole struct are reported at its own path
+        let path = match (prefix, *field)
+        {
+            ("", field) => field.to_string(),
+            (prefix, "__all__") => prefix.to_string(),
+            (prefix, field) => format!("{}.{}", prefix, field),
+        };
+
+        match kind
+        {
+            ValidationErrorsKind::Field(errors) =>
+            {
+                let errors = errors.iter().map(|error| FieldError {
+                    code: error.code.to_string(),
+                    message: error
+                        .message
+                        .as_ref()
+                        .map_or_else(|| error.code.to_string(), |message| message.to_string()),
+                });
+
+                fields.entry(path).or_default().extend(errors);
+            },
+            ValidationErrorsKind::Struct(errors) => collect(errors, &path, fields),
+            ValidationErrorsKind::List(items) =>
+            {
+                for (index, errors) in items
+                {
+                    collect(errors, &format!("{}[{}]", path, index), fields);
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use validator::Validate;
+
+    use super::*;
+    use crate::orders::model::{ModifierChoice, Note, OrderCreateRequest, ProductView};
+    use crate::products::model::{ProductCreateRequest, ProductKind};
+
+    #[test]
+    fn reports_nested_fields_by_path()
+    {
+        let request = OrderCreateRequest {
+            products: vec![
+                ProductView {
+                    id: ObjectId::new().to_hex(),
+                    quantity: 1,
+                    modifiers: vec![],
+                    note: None,
+                },
+                ProductView {
+                    id: "x".to_string(),
+                    quantity: 0,
+                    modifiers: vec![ModifierChoice {
+                        group: " ".to_string(),
+                        option: "Large".to_string(),
+                    }],
+                    note: None,
+                },
+            ],
+            note: Some(Note { text: "".to_string(), allergy: true }),
+        };
+
+        let fields = field_errors(&request.validate().unwrap_err());
+
+        let paths: Vec<&String> = fields.keys().collect();
+
+        assert_eq!(
+            paths,
+            [
+                "note.text",
+                "products[1].id",
+                "products[1].modifiers[0].group",
+                "products[1].quantity"
+            ]
+        );
+        assert_eq!(fields["products[1].quantity"][0].message, "must be at least 1");
+    }
+
+    #[test]
+    fn rejects_negative_prices()
+    {
+        for price in ["-0.50", "-1 EUR"]
+        {
+            let request = ProductCreateRequest {
+                name: "Soup".to_string(),
+                price: price.parse().unwrap(),
+                kind: ProductKind::Food,
+                station: None,
+                category: None,
+                position: None,
+                modifiers: Vec::new(),
+                recipe: None,
+                allergens: vec![],
+                dietary: vec![],
+            };
+
+            let fields = field_errors(&request.validate().unwrap_err());
+
+            assert_eq!(fields["price"][0].code, "price");
+        }
+    }
+}